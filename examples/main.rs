@@ -1,18 +1,20 @@
 use std::sync::Arc;
 use vesania::bezier::line::Line;
 use vesania::bezier::quadratic::Quadratic;
+use vesania::codec::png::PngOptions;
 use vesania::fills;
-use vesania::layer::{Image, Layer, Shader};
+use vesania::layer::{Layer, Shader};
 use vesania::path::Path;
-use vesania::render::{FillRule, Renderer};
+use vesania::render::FillRule;
+use vesania::scene::{Entry, Scene};
 use vesania::shape::Shape;
 use vesania::bezier::Bezier;
-use glam::Vec2;
-use rgb::{Pixel, Rgba};
+use glam::UVec2;
+use rgb::Rgba;
 
 fn main() {
-    let mut my_canvas = Canvas::new(3000, 3000);
-    my_canvas.fill_with(Rgba::from((255, 255, 255, 255)));
+    let mut scene = Scene::new(UVec2::new(3000, 3000)).unwrap();
+    scene.set_background(Rgba::from([1.0, 1.0, 1.0, 1.0]));
 
     let quad1 = Quadratic::new([400.0, 100.0].into(), [100.0, 100.0].into(), [100.0, 400.0].into());
     let quad2 = Quadratic::new([100.0, 400.0].into(), [100.0, 700.0].into(), [400.0, 700.0].into());
@@ -32,58 +34,8 @@ fn main() {
     //let my_material = fills::Radial::new([0.1, 1.0, 1.0, 1.0], [0.4, 1.0, 0.2, 1.0], [0.1, 0.1], 0.2);
     let my_material = fills::Radial::new([1.0, 0.0, 0.0, 1.0], [0.0, 1.0, 0.0, 1.0], [0.1, 0.1], 0.2);
 
-    let rend = Renderer::new(path, Vec2::from([3000., 3000.]), FillRule::NonZero, &my_material);
-    let img = rend.render();
+    scene.push(Entry::new(path, FillRule::NonZero, &my_material));
+    let img = scene.render();
 
-    my_canvas.image(img.paint());
-    my_canvas.write_to_png("out.png").unwrap();
-    
+    img.save_png("out.png", PngOptions::default()).unwrap();
 }
-
-pub struct Canvas {
-    size: (u16, u16), // 32k by 32k is quite big!
-    buffer: Vec<u32>,
-}
-
-impl<'pix> Canvas {
-    pub fn new(w: u16, h: u16) -> Canvas {
-        return Canvas {
-            size: (w, h),
-            buffer: vec![0u32; w as usize * h as usize]
-        }
-    }
-
-    pub fn write_to_png<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<(), lodepng::Error> {
-        self.to_be();
-        lodepng::encode32_file(path, &self.buffer, self.size.0.into(), self.size.1.into())
-    }
-
-    pub fn pixel_at(&mut self, x: u16, y: u16) -> &mut u32 {
-        return self.buffer.get_mut((y * self.size.0 + x) as usize).unwrap()
-    }
-
-    pub fn pixel_at_index(&mut self, i: usize) -> &mut u32 {
-        return self.buffer.get_mut(i).unwrap()
-    }
-
-    pub fn fill_with(&mut self, col: Rgba<u8>) {
-        let col = unsafe {
-            std::mem::transmute::<Rgba<u8>, u32>(col)
-        };
-        self.buffer.iter_mut()
-            .for_each(|pix| *pix = col);
-    }
-
-    pub fn to_be(&mut self) {
-        self.buffer.iter_mut()
-            .for_each(|pix| *pix = pix.to_be());
-    }
-
-    pub fn image(&mut self, img: Image) {
-        for (i, pix) in self.buffer.iter_mut().enumerate() {
-            *pix = unsafe {
-                std::mem::transmute::<Rgba<u8>, u32>(img.pixels.get(i).unwrap_or(&Rgba { r: 1.0, g: 1.0, b: 1.0, a: 1.0 }).map(|col| (col * 255.0) as u8)).to_be()
-            }
-        }
-    }
-}
\ No newline at end of file
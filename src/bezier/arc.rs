@@ -0,0 +1,232 @@
+use std::{f32::consts::TAU, sync::Arc};
+
+use glam::Vec2;
+
+use super::{line::Line, quadratic::Quadratic, Bezier};
+
+/// # Elliptic arc
+/// A piece of an ellipse, described by its center.
+///
+/// Points of the arc are found at the angles from
+/// `start` to `start + sweep` on a circle, stretched
+/// by `radii` and turned by `rotation`. Like every
+/// other angle, they are in radians and positive
+/// ones turn clockwise on screen.
+///
+/// Arcs are drawn by turning them into quadratics,
+/// see [`EllipticArc::to_quadratics`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EllipticArc {
+    pub center: Vec2,
+    pub radii: Vec2,
+    pub rotation: f32,
+    pub start: f32,
+    pub sweep: f32,
+}
+
+impl EllipticArc {
+    pub fn new(center: Vec2, radii: Vec2, rotation: f32, start: f32, sweep: f32) -> EllipticArc {
+        return EllipticArc { center, radii, rotation, start, sweep };
+    }
+
+    /// # Circular
+    /// An arc of the circle centered on `center`.
+    pub fn circular(center: Vec2, radius: f32, start: f32, sweep: f32) -> EllipticArc {
+        return EllipticArc::new(center, Vec2::splat(radius), 0.0, start, sweep);
+    }
+
+    /// # From endpoints
+    /// The arc of an SVG `A` command, going from `from`
+    /// to `to` along an ellipse with the given radii,
+    /// turned by `rotation`. Out of the four arcs that
+    /// fit, `large_arc` picks the ones spanning more
+    /// than half a turn over the others, and `sweep`
+    /// the ones going clockwise on screen.
+    ///
+    /// Radii too small to reach `to` are scaled up
+    /// until they do, and negative ones are made
+    /// positive. Returns `None` when the endpoints are
+    /// the same, where the arc is skipped, or when
+    /// a radius is zero, where the arc is drawn as a
+    /// straight line; see [`svg_arc`].
+    pub fn from_endpoints(from: Vec2, to: Vec2, radii: Vec2, rotation: f32, large_arc: bool, sweep: bool) -> Option<EllipticArc> {
+        // As in the implementation notes of the SVG specification
+        // (F.6.5 and F.6.6).
+        let mut radii = radii.abs();
+        if from == to || radii.x == 0.0 || radii.y == 0.0 {
+            return None;
+        }
+        let axis = Vec2::from_angle(rotation);
+        // `from`, in a space centered on the middle of the chord
+        // where the axes of the ellipse are aligned with x and y.
+        let p = Vec2::new(axis.x, -axis.y).rotate((from - to) / 2.0);
+
+        let lambda = (p / radii).length_squared();
+        if lambda > 1.0 {
+            radii *= lambda.sqrt();
+        }
+        let (rx2, ry2) = (radii.x * radii.x, radii.y * radii.y);
+        let (px2, py2) = (p.x * p.x, p.y * p.y);
+        let sign = if large_arc != sweep { 1.0 } else { -1.0 };
+        let coef = sign * f32::max((rx2 * ry2 - rx2 * py2 - ry2 * px2) / (rx2 * py2 + ry2 * px2), 0.0).sqrt();
+        let c = coef * Vec2::new(radii.x * p.y / radii.y, -radii.y * p.x / radii.x);
+        let center = axis.rotate(c) + (from + to) / 2.0;
+
+        let u = (p - c) / radii;
+        let v = (-p - c) / radii;
+        let start = Vec2::X.angle_to(u);
+        let mut delta = u.angle_to(v);
+        if sweep && delta < 0.0 {
+            delta += TAU;
+        } else if !sweep && delta > 0.0 {
+            delta -= TAU;
+        }
+        return Some(EllipticArc::new(center, radii, rotation, start, delta));
+    }
+
+    /// # Point
+    /// The point of the arc at the angle `angle`.
+    pub fn point(&self, angle: f32) -> Vec2 {
+        let local = Vec2::from_angle(angle) * self.radii;
+        return self.center + Vec2::from_angle(self.rotation).rotate(local);
+    }
+
+    pub fn start_point(&self) -> Vec2 {
+        return self.point(self.start);
+    }
+
+    pub fn end_point(&self) -> Vec2 {
+        return self.point(self.start + self.sweep);
+    }
+
+    /// # To quadratics
+    /// Approximates the arc with quadratics, staying
+    /// within `tolerance` of it, ready to be placed in
+    /// a [`crate::path::Path`]. Arcs of a whole turn
+    /// or more are drawn as the whole ellipse.
+    pub fn to_quadratics(&self, tolerance: f32) -> Vec<Arc<dyn Bezier>> {
+        let axis = Vec2::from_angle(self.rotation);
+        let sweep = self.sweep.clamp(-TAU, TAU);
+        let point = |t: f32| self.point(t);
+        let tangent = |t: f32| axis.rotate(Vec2::new(-t.sin(), t.cos()) * self.radii);
+        let quads = Quadratic::approximate(point, tangent, self.start, self.start + sweep, tolerance);
+        // The rasterizer needs curves going either up or down.
+        return quads.iter().flat_map(|quad| quad.fix()).collect();
+    }
+}
+
+/// # SVG arc
+/// The curves drawing an SVG `A` command from `from`
+/// to `to`, within `tolerance`, following the rules
+/// of the specification for degenerate arcs: nothing
+/// when the endpoints are the same, a straight line
+/// when a radius is zero. See
+/// [`EllipticArc::from_endpoints`] for the others.
+pub fn svg_arc(from: Vec2, to: Vec2, radii: Vec2, rotation: f32, large_arc: bool, sweep: bool, tolerance: f32) -> Vec<Arc<dyn Bezier>> {
+    if from == to {
+        return vec![];
+    }
+    return match EllipticArc::from_endpoints(from, to, radii, rotation, large_arc, sweep) {
+        Some(arc) => arc.to_quadratics(tolerance),
+        None => Line::new(from, to).fix(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use glam::Vec2;
+
+    use super::{svg_arc, EllipticArc};
+
+    fn assert_near(a: Vec2, b: Vec2) {
+        assert!(a.distance(b) < 1e-3, "{a} != {b}");
+    }
+
+    #[test]
+    fn endpoints_flags() {
+        let (from, to) = (Vec2::new(0.0, 0.0), Vec2::new(10.0, 0.0));
+        let below = Vec2::new(5.0, 75f32.sqrt());
+        let above = Vec2::new(5.0, -75f32.sqrt());
+        // With y pointing down, sweeping clockwise on screen
+        // from `from` to `to` goes over the top of the circle.
+        let cases = [
+            (false, false, above),
+            (false, true, below),
+            (true, false, below),
+            (true, true, above),
+        ];
+        for (large_arc, sweep, center) in cases {
+            let arc = EllipticArc::from_endpoints(from, to, Vec2::splat(10.0), 0.0, large_arc, sweep).unwrap();
+            assert_near(arc.center, center);
+            assert_near(arc.start_point(), from);
+            assert_near(arc.end_point(), to);
+            assert_eq!(arc.sweep.abs() > PI, large_arc);
+            assert_eq!(arc.sweep > 0.0, sweep);
+        }
+    }
+
+    #[test]
+    fn endpoints_rotated() {
+        let (from, to) = (Vec2::new(3.0, -4.0), Vec2::new(12.0, 7.0));
+        let arc = EllipticArc::from_endpoints(from, to, Vec2::new(20.0, 8.0), 0.7, true, false).unwrap();
+        assert_eq!(arc.radii, Vec2::new(20.0, 8.0));
+        assert_near(arc.start_point(), from);
+        assert_near(arc.end_point(), to);
+    }
+
+    #[test]
+    fn endpoints_small_radii() {
+        // Radii too small to reach the other end are scaled up
+        // until the chord is a diameter, which leaves a single
+        // center for every flag.
+        let (from, to) = (Vec2::new(0.0, 0.0), Vec2::new(10.0, 0.0));
+        for (large_arc, sweep) in [(false, false), (false, true), (true, false), (true, true)] {
+            let arc = EllipticArc::from_endpoints(from, to, Vec2::new(2.0, -2.0), 0.0, large_arc, sweep).unwrap();
+            assert!((arc.radii - Vec2::splat(5.0)).abs().max_element() < 1e-4);
+            assert_near(arc.center, Vec2::new(5.0, 0.0));
+            assert!((arc.sweep.abs() - PI).abs() < 1e-3);
+            assert_near(arc.end_point(), to);
+        }
+
+        // Ellipses keep their proportions.
+        let arc = EllipticArc::from_endpoints(from, Vec2::new(0.0, 10.0), Vec2::new(1.0, 2.0), 0.0, false, true).unwrap();
+        assert!((arc.radii.x * 2.0 - arc.radii.y).abs() < 1e-4);
+        assert_near(arc.end_point(), Vec2::new(0.0, 10.0));
+    }
+
+    #[test]
+    fn endpoints_degenerate() {
+        let (from, to) = (Vec2::new(1.0, 2.0), Vec2::new(6.0, 9.0));
+        // A zero radius is drawn as a straight line.
+        for radii in [Vec2::new(0.0, 5.0), Vec2::new(5.0, 0.0)] {
+            assert!(EllipticArc::from_endpoints(from, to, radii, 0.0, false, true).is_none());
+            let curves = svg_arc(from, to, radii, 0.0, false, true, 0.1);
+            assert_eq!(curves.len(), 1);
+            assert_eq!((*curves[0].first_point(), *curves[0].last_point()), (from, to));
+        }
+        // Equal endpoints draw nothing.
+        assert!(EllipticArc::from_endpoints(from, from, Vec2::splat(5.0), 0.0, true, true).is_none());
+        assert!(svg_arc(from, from, Vec2::splat(5.0), 0.0, true, true, 0.1).is_empty());
+    }
+
+    #[test]
+    fn quadratics_within_tolerance() {
+        let arc = EllipticArc::new(Vec2::new(50.0, 40.0), Vec2::new(30.0, 20.0), 0.4, 0.3, 4.0);
+        for tolerance in [1.0, 0.1, 0.01] {
+            let quads = arc.to_quadratics(tolerance);
+            assert_near(*quads[0].first_point(), arc.start_point());
+            assert_near(*quads[quads.len() - 1].last_point(), arc.end_point());
+            for quad in &quads {
+                for i in 0..=16 {
+                    // The distance to the ellipse along a ray from its
+                    // center, a little longer than the shortest one.
+                    let p = Vec2::from_angle(-arc.rotation).rotate(quad.t(i as f32 / 16.0) - arc.center);
+                    let on = p / (p / arc.radii).length();
+                    assert!(p.distance(on) <= tolerance * 1.1, "{} > {tolerance}", p.distance(on));
+                }
+            }
+        }
+    }
+}
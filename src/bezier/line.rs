@@ -1,5 +1,5 @@
 use std::sync::Arc;
-use glam::{Vec2, Vec4};
+use glam::{Affine2, Vec2, Vec4};
 use crate::shape::Shape;
 
 use super::{lerp, Bezier};
@@ -62,6 +62,13 @@ impl Bezier for Line {
         ]
     }
 
+    fn transform(&self, transform: Affine2) -> Arc<dyn Bezier> {
+        return Arc::new(Line::new(
+            transform.transform_point2(self.a),
+            transform.transform_point2(self.b),
+        ));
+    }
+
     fn fix(&self) -> Vec<Arc<dyn Bezier>> {
         if self.a.y == self.b.y { return vec![]; } // Erase the line
         return vec![Arc::new(Line::new(self.a, self.b))];
@@ -81,13 +88,9 @@ impl Bezier for Line {
 
 impl Shape for Line {
     fn intersections(&self, p: Vec2) -> Vec<f32> {
-        if p.y > f32::max(self.a.y, self.b.y) { return vec![]; }
-        if self.a.x == self.b.x {
-            return vec![self.a.x];
-        } else {
-            let m = (self.b.y - self.a.y) / (self.b.x - self.a.x);
-            let i = (p.y - self.a.y) / m + self.a.x;
-            return vec![i];
-        }
+        if self.a.y == self.b.y { return vec![]; } // Parallel to the ray
+        let t = (p.y - self.a.y) / (self.b.y - self.a.y);
+        if t < 0.0 || t > 1.0 { return vec![]; }
+        return vec![t];
     }
 }
\ No newline at end of file
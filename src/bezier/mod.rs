@@ -1,11 +1,12 @@
 use std::sync::Arc;
 use std::fmt::Debug;
-use glam::{Mat2, Vec2, Vec4};
+use glam::{Affine2, Mat2, Vec2, Vec4};
 
 use crate::shape::Shape;
 
 pub mod quadratic;
 pub mod line;
+pub mod arc;
 
 #[derive(Debug, PartialEq, PartialOrd)]
 pub enum Direction {
@@ -111,6 +112,13 @@ pub trait Bezier: Shape + Debug {
     /// Splits the curve at `t` into multiple curves.
     fn split(&self, t: f32) -> Vec<Arc<dyn Bezier>>;
 
+    /// # Transform
+    /// Returns a copy of the curve with every point
+    /// mapped through `transform`. Since beziers are
+    /// affine invariant, transforming the control
+    /// points is enough.
+    fn transform(&self, transform: Affine2) -> Arc<dyn Bezier>;
+
     /// # Fix
     /// Returns either the same curve, a different
     /// one, nothing or two separate ones, depending
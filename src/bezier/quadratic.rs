@@ -1,10 +1,14 @@
 use std::sync::Arc;
 
-use glam::{Mat2, Vec2, Vec4};
+use glam::{Affine2, Mat2, Vec2, Vec4};
 use crate::shape::Shape;
 
 use super::{lerp, line::Line, Bezier};
 
+// Curves are halved this many times at most, to guard
+// against tolerances that can't be met.
+const MAX_DEPTH: u32 = 14;
+
 /// # Quadratic Bezier curve
 /// A 2nd degree Bezier curve defined by three
 /// point, named `a`, `b` and `c`.
@@ -20,6 +24,25 @@ impl Quadratic {
         return Quadratic { a, b, c };
     }
 
+    /// # Approximate
+    /// Approximates the parametric curve `point`, from
+    /// `t0` to `t1`, with as few quadratics as needed
+    /// for them to stay within `tolerance` of it.
+    /// `tangent` must point along the derivative of
+    /// `point`, but can have any length.
+    /// 
+    /// The curve is halved until the quadratic whose
+    /// control point sits where the tangents at both
+    /// ends meet is close enough to each piece.
+    /// Quadratics going up and then down, or the other
+    /// way around, are left as they are; see
+    /// [`Bezier::fix`].
+    pub fn approximate(point: impl Fn(f32) -> Vec2, tangent: impl Fn(f32) -> Vec2, t0: f32, t1: f32, tolerance: f32) -> Vec<Quadratic> {
+        let mut out = vec![];
+        approximate(&point, &tangent, t0, t1, tolerance, MAX_DEPTH, &mut out);
+        return out;
+    }
+
     pub fn is_line(&self) -> bool {
         if (self.b.y - self.a.y) * (self.c.x - self.b.x)
         == (self.c.y - self.b.y) * (self.b.x - self.a.x) {
@@ -126,20 +149,27 @@ impl Bezier for Quadratic {
 
         return vec![
             Arc::new(Quadratic::new(self.a, d, f)),
-            Arc::new(Quadratic::new(f, e, self.b)),
+            Arc::new(Quadratic::new(f, e, self.c)),
         ]
     }
 
+    fn transform(&self, transform: Affine2) -> Arc<dyn Bezier> {
+        return Arc::new(Quadratic::new(
+            transform.transform_point2(self.a),
+            transform.transform_point2(self.b),
+            transform.transform_point2(self.c),
+        ));
+    }
+
     fn fix(&self) -> Vec<Arc<dyn Bezier>> {
         // if the control point is either above or below the two other points, the
         // curve will certainly contain a point with a slope of zero, so we split
         // the curve in two at that point.
-        if self.b.y > self.bb().w || self.b.y < self.bb().y {
-            let t = (2.0*self.a - 2.0*self.b)/(2.0*self.a - 4.0*self.b + 2.0*self.c);
-            // t is a Vec2. Technically each component should be equal, but y'know,
-            // floating point math sucks.
-            assert!(t.x == t.y);
-            return self.split(t.x);
+        if self.b.y > f32::max(self.a.y, self.c.y) || self.b.y < f32::min(self.a.y, self.c.y) {
+            // Only the vertical slope matters: the x component of the
+            // derivative vanishes somewhere else entirely.
+            let t = (self.a.y - self.b.y) / (self.a.y - 2.0*self.b.y + self.c.y);
+            return self.split(t);
         } else if self.is_line() {
             // NOTE: a curve should never be colinear! If it is, maybe the control
             // point just lies between the other two points.
@@ -154,6 +184,56 @@ impl Bezier for Quadratic {
     }
 }
 
+fn approximate(
+    point: &impl Fn(f32) -> Vec2,
+    tangent: &impl Fn(f32) -> Vec2,
+    t0: f32,
+    t1: f32,
+    tolerance: f32,
+    depth: u32,
+    out: &mut Vec<Quadratic>,
+) {
+    let (a, c) = (point(t0), point(t1));
+    let (da, dc) = (tangent(t0), tangent(t1));
+    let cross = da.perp_dot(dc);
+    // Parallel tangents never meet, so the piece is either straight,
+    // or turns by half a circle and will be split anyway.
+    let b = if cross.abs() > f32::EPSILON * da.length() * dc.length() {
+        a + da * (c - a).perp_dot(dc) / cross
+    } else {
+        (a + c) / 2.0
+    };
+    let quad = Quadratic::new(a, b, c);
+
+    if depth == 0 || error(&quad, point, t0, t1) <= tolerance {
+        out.push(quad);
+        return;
+    }
+    let mid = (t0 + t1) / 2.0;
+    approximate(point, tangent, t0, mid, tolerance, depth - 1, out);
+    approximate(point, tangent, mid, t1, tolerance, depth - 1, out);
+}
+
+// Estimates how far `quad` strays from the curve between `t0` and
+// `t1`, comparing fine polylines following both. Distances are
+// measured both ways, since a quadratic covering only part of the
+// curve is close to it but not the other way around.
+fn error(quad: &Quadratic, point: &impl Fn(f32) -> Vec2, t0: f32, t1: f32) -> f32 {
+    const SAMPLES: usize = 16;
+    let curve = (0..=SAMPLES).map(|i| point(lerp(t0, t1, i as f32 / SAMPLES as f32))).collect::<Vec<Vec2>>();
+    let quad = (0..=SAMPLES).map(|i| quad.t(i as f32 / SAMPLES as f32)).collect::<Vec<Vec2>>();
+    return f32::max(distance(&quad, &curve), distance(&curve, &quad));
+}
+
+// The largest distance from the points of `from` to the polyline `to`.
+fn distance(from: &[Vec2], to: &[Vec2]) -> f32 {
+    return from.iter().map(|p| to.windows(2).map(|segment| {
+        let (a, b) = (segment[0], segment[1]);
+        let t = ((*p - a).dot(b - a) / (b - a).length_squared().max(f32::MIN_POSITIVE)).clamp(0.0, 1.0);
+        return p.distance(a + (b - a) * t);
+    }).fold(f32::INFINITY, f32::min)).fold(0.0, f32::max);
+}
+
 // TODO: Add Epsilon values because floating point math sucks
 impl Shape for Quadratic {
     fn intersections(&self, p: Vec2) -> Vec<f32> {
@@ -169,6 +249,13 @@ impl Shape for Quadratic {
         let a = self.a.y - 2.0*self.b.y + self.c.y;
         let b = 2.0 * (self.b.y - self.a.y);
         let c = self.a.y - p.y;
+        if a == 0.0 {
+            // The y component is linear, so there's at most one solution.
+            if b == 0.0 { return vec![]; }
+            let t = -c / b;
+            if t <= 1.0 && t >= 0.0 { inters.push(t) }
+            return inters;
+        }
         let delta = b*b - 4.0*a*c;
 
         if delta <= -0.0001 { return vec![]; } // No intersections; Because of precision, delta can be negative.
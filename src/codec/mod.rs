@@ -0,0 +1,123 @@
+//! # Codec
+//! Reading and writing [`Image`]s from and to
+//! common file formats.
+//! 
+//! Files store straight (non premultiplied) colors,
+//! so pixels are unpremultiplied when encoded.
+//! 
+//! [`Image`]: crate::layer::Image
+
+use std::fmt::{self, Display};
+
+use glam::UVec2;
+use rgb::Rgba;
+
+use crate::size::{area, SizeError};
+
+pub mod png;
+pub mod pnm;
+pub mod qoi;
+pub mod raw;
+
+#[cfg(test)]
+mod tests;
+
+/// # Bit depth
+/// The number of bits per channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BitDepth {
+    #[default]
+    Eight,
+    Sixteen,
+}
+
+impl BitDepth {
+    pub fn bits(&self) -> u32 {
+        return match self {
+            BitDepth::Eight => 8,
+            BitDepth::Sixteen => 16,
+        };
+    }
+
+    /// The largest value a channel can hold.
+    pub fn max(&self) -> u32 {
+        return (1 << self.bits()) - 1;
+    }
+}
+
+/// # Error
+/// Everything that can go wrong while encoding or
+/// decoding an image.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing failed.
+    Io(std::io::Error),
+    /// The PNG encoder or decoder failed.
+    Png(lodepng::Error),
+    /// The size of the image is zero or too large,
+    /// or doesn't match the number of pixels.
+    Dimensions,
+    /// The data is malformed, or uses a feature
+    /// that isn't supported.
+    Format(&'static str),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Error::Io(err) => write!(f, "i/o error: {err}"),
+            Error::Png(err) => write!(f, "png error: {err}"),
+            Error::Dimensions => write!(f, "the size of the image is invalid or doesn't match its pixels"),
+            Error::Format(reason) => write!(f, "invalid data: {reason}"),
+        };
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        return match self {
+            Error::Io(err) => Some(err),
+            Error::Png(err) => Some(err),
+            _ => None,
+        };
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        return Error::Io(err);
+    }
+}
+
+impl From<SizeError> for Error {
+    fn from(_: SizeError) -> Error {
+        return Error::Dimensions;
+    }
+}
+
+impl From<lodepng::Error> for Error {
+    fn from(err: lodepng::Error) -> Error {
+        return Error::Png(err);
+    }
+}
+
+// The size of a decoded image, which must fit in the
+// `u32`s of a `UVec2` and pass the checks of `area`.
+fn size(width: usize, height: usize) -> Result<UVec2, Error> {
+    let width = u32::try_from(width).map_err(|_| Error::Dimensions)?;
+    let height = u32::try_from(height).map_err(|_| Error::Dimensions)?;
+    let size = UVec2::new(width, height);
+    area(size)?;
+    return Ok(size);
+}
+
+// Scales a channel in [0, 1] to an integer in [0, max].
+fn quantize(c: f32, max: f32) -> f32 {
+    return (c.clamp(0.0, 1.0) * max).round();
+}
+
+// The luma of a straight pixel, with the Rec. 709 weights,
+// used when writing grey images.
+fn luma(pixel: Rgba<f32>) -> f32 {
+    return 0.2126 * pixel.r + 0.7152 * pixel.g + 0.0722 * pixel.b;
+}
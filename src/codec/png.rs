@@ -0,0 +1,260 @@
+//! # PNG
+//! Encoding and decoding of [`Image`]s to and from
+//! PNG, through `lodepng`.
+
+use std::{borrow::Cow, fs::File, io::{BufWriter, Read, Write}, path::Path};
+
+use glam::UVec2;
+use lodepng::{Bitmap, Decoder, Encoder};
+use rgb::Rgba;
+
+use crate::{color::{linear_to_srgb, ColorSpace}, layer::Image};
+use super::{luma, quantize, size, BitDepth, Error};
+
+/// # Color type
+/// The channels written to the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorType {
+    #[default]
+    Rgba,
+    Rgb,
+    /// The luma of the color, with the Rec. 709
+    /// weights.
+    Grey,
+    GreyAlpha,
+}
+
+impl ColorType {
+    fn channels(&self) -> usize {
+        return match self {
+            ColorType::Rgba => 4,
+            ColorType::Rgb => 3,
+            ColorType::Grey => 1,
+            ColorType::GreyAlpha => 2,
+        };
+    }
+
+    fn lodepng(&self) -> lodepng::ColorType {
+        return match self {
+            ColorType::Rgba => lodepng::ColorType::RGBA,
+            ColorType::Rgb => lodepng::ColorType::RGB,
+            ColorType::Grey => lodepng::ColorType::GREY,
+            ColorType::GreyAlpha => lodepng::ColorType::GREY_ALPHA,
+        };
+    }
+
+    // Writes the channels of a straight pixel, each
+    // in [0, 1].
+    fn channels_of(&self, pixel: Rgba<f32>, out: &mut Vec<f32>) {
+        match self {
+            ColorType::Rgba => out.extend([pixel.r, pixel.g, pixel.b, pixel.a]),
+            ColorType::Rgb => out.extend([pixel.r, pixel.g, pixel.b]),
+            ColorType::Grey => out.push(luma(pixel)),
+            ColorType::GreyAlpha => out.extend([luma(pixel), pixel.a]),
+        }
+    }
+}
+
+/// # Png options
+/// How an image is written. The default is
+/// 8 bit RGBA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PngOptions {
+    pub color_type: ColorType,
+    pub bit_depth: BitDepth,
+    /// Whether to store linear sRGB samples, with a
+    /// gAMA chunk of 1. Only 16 bit files are written
+    /// linear, since 8 bits band in the shadows.
+    pub linear: bool,
+}
+
+impl PngOptions {
+    pub fn new(color_type: ColorType, bit_depth: BitDepth) -> PngOptions {
+        return PngOptions { color_type, bit_depth, linear: false };
+    }
+
+    pub fn set_linear(&mut self, linear: bool) {
+        self.linear = linear;
+    }
+}
+
+impl Image {
+    /// # Encode PNG
+    /// Encodes the image to PNG, in memory. Colors
+    /// are written as they are, in the color space
+    /// the image is tagged with, along with its ICC
+    /// profile if it has one. Without a profile,
+    /// images in other color spaces are converted
+    /// to sRGB, or to linear sRGB for 16 bit files
+    /// with [`PngOptions::linear`] set.
+    pub fn encode_png(&self, options: PngOptions) -> Result<Vec<u8>, Error> {
+        let (width, height) = (self.size.x as usize, self.size.y as usize);
+        if self.pixels.len() != width * height {
+            return Err(Error::Dimensions);
+        }
+        let linear = options.linear && options.bit_depth == BitDepth::Sixteen && self.icc.is_none();
+        let space = if linear { ColorSpace::LinearSrgb } else { ColorSpace::Srgb };
+        let image = if self.color_space == space || self.icc.is_some() {
+            Cow::Borrowed(self)
+        } else {
+            let mut image = self.clone();
+            image.convert(space);
+            Cow::Owned(image)
+        };
+
+        let mut channels = Vec::with_capacity(image.pixels.len() * options.color_type.channels());
+        for pixel in image.to_straight() {
+            options.color_type.channels_of(pixel, &mut channels);
+        }
+        // lodepng wants 16 bit samples in big endian order.
+        let buffer: Vec<u8> = match options.bit_depth {
+            BitDepth::Eight => channels.iter().map(|c| quantize(*c, 255.0) as u8).collect(),
+            BitDepth::Sixteen => channels.iter().flat_map(|c| (quantize(*c, 65535.0) as u16).to_be_bytes()).collect(),
+        };
+        let depth = options.bit_depth.bits();
+
+        let mut encoder = Encoder::new();
+        encoder.set_auto_convert(false);
+        encoder.info_raw_mut().colortype = options.color_type.lodepng();
+        encoder.info_raw_mut().set_bitdepth(depth);
+        encoder.info_png_mut().color.colortype = options.color_type.lodepng();
+        encoder.info_png_mut().color.set_bitdepth(depth);
+        if let Some(icc) = &image.icc {
+            encoder.info_png_mut().set_icc(icc)?;
+        } else if linear {
+            // Tells readers the samples are linear light.
+            encoder.info_png_mut().gama_defined = 1;
+            encoder.info_png_mut().gama_gamma = 100000;
+        }
+        return Ok(encoder.encode(&buffer, width, height)?);
+    }
+
+    /// # Write PNG
+    /// Encodes the image to PNG into `writer`.
+    pub fn write_png<W: Write>(&self, mut writer: W, options: PngOptions) -> Result<(), Error> {
+        writer.write_all(&self.encode_png(options)?)?;
+        return Ok(());
+    }
+
+    /// # Save PNG
+    /// Encodes the image to a PNG file at `path`.
+    pub fn save_png<P: AsRef<Path>>(&self, path: P, options: PngOptions) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_png(&mut writer, options)?;
+        writer.flush()?;
+        return Ok(());
+    }
+}
+
+impl Image {
+    /// # Decode PNG
+    /// Decodes a PNG held in memory. Every color type
+    /// and bit depth is normalized to `Rgba<f32>`,
+    /// keeping the full precision of 16 bit files, and
+    /// the pixels are premultiplied.
+    /// 
+    /// The image is tagged as sRGB, unless the file
+    /// only carries a gAMA chunk: samples with a gamma
+    /// of 1 are tagged as linear, others are re-encoded
+    /// as sRGB. An embedded ICC profile is attached to
+    /// the image as is.
+    pub fn decode_png(bytes: &[u8]) -> Result<Image, Error> {
+        let mut decoder = Decoder::new();
+        // lodepng converts to 8 bit RGBA unless told otherwise,
+        // so ask for the channels and depth of the file instead.
+        // Palettes are expanded to RGBA, and depths below 8
+        // are widened to 8. A tRNS chunk makes one grey level
+        // or color transparent, so those files need alpha.
+        let (header, _, _) = decoder.inspect(bytes)?;
+        let key = has_transparency(bytes);
+        let colortype = match header.color.colortype {
+            lodepng::ColorType::GREY if !key => lodepng::ColorType::GREY,
+            lodepng::ColorType::GREY | lodepng::ColorType::GREY_ALPHA => lodepng::ColorType::GREY_ALPHA,
+            lodepng::ColorType::RGB if !key => lodepng::ColorType::RGB,
+            _ => lodepng::ColorType::RGBA,
+        };
+        let depth = if header.color.bitdepth() == 16 { 16 } else { 8 };
+        decoder.info_raw_mut().colortype = colortype;
+        decoder.info_raw_mut().set_bitdepth(depth);
+        let decoded = decoder.decode(bytes)?;
+        let n8 = |c: u8| c as f32 / 255.0;
+        let n16 = |c: u16| c as f32 / 65535.0;
+        let (size, mut pixels) = match decoded {
+            lodepng::Image::RGBA(bitmap) => straight(&bitmap, |p| [n8(p.r), n8(p.g), n8(p.b), n8(p.a)])?,
+            lodepng::Image::RGB(bitmap) => straight(&bitmap, |p| [n8(p.r), n8(p.g), n8(p.b), 1.0])?,
+            lodepng::Image::RGBA16(bitmap) => straight(&bitmap, |p| [n16(p.r), n16(p.g), n16(p.b), n16(p.a)])?,
+            lodepng::Image::RGB16(bitmap) => straight(&bitmap, |p| [n16(p.r), n16(p.g), n16(p.b), 1.0])?,
+            lodepng::Image::Grey(bitmap) => straight(&bitmap, |p| [n8(p.0), n8(p.0), n8(p.0), 1.0])?,
+            lodepng::Image::Grey16(bitmap) => straight(&bitmap, |p| [n16(p.0), n16(p.0), n16(p.0), 1.0])?,
+            lodepng::Image::GreyAlpha(bitmap) => straight(&bitmap, |p| [n8(p.0), n8(p.0), n8(p.0), n8(p.1)])?,
+            lodepng::Image::GreyAlpha16(bitmap) => straight(&bitmap, |p| [n16(p.0), n16(p.0), n16(p.0), n16(p.1)])?,
+            // Only returned for depths other than 8 and 16.
+            lodepng::Image::RawData(_) => return Err(Error::Format("unexpected png bit depth")),
+        };
+
+        let info = decoder.info_png();
+        let mut color_space = ColorSpace::Srgb;
+        // sRGB and iCCP chunks take precedence over gAMA.
+        if info.gama_defined != 0 && info.srgb_defined == 0 && info.iccp_defined == 0 && info.gama_gamma != 0 {
+            if info.gama_gamma == 100000 {
+                color_space = ColorSpace::LinearSrgb;
+            } else {
+                let exponent = 100000.0 / info.gama_gamma as f32;
+                let encode = |c: f32| linear_to_srgb(c.powf(exponent));
+                for pixel in pixels.iter_mut() {
+                    *pixel = Rgba::from([encode(pixel.r), encode(pixel.g), encode(pixel.b), pixel.a]);
+                }
+            }
+        }
+
+        let mut image = Image::from_straight(size, pixels);
+        image.color_space = color_space;
+        if info.iccp_defined != 0 {
+            if let Ok(icc) = info.get_icc() {
+                image.set_icc(&icc);
+            }
+        }
+        return Ok(image);
+    }
+
+    /// # Read PNG
+    /// Decodes a PNG read from `reader`.
+    pub fn read_png<R: Read>(mut reader: R) -> Result<Image, Error> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        return Image::decode_png(&bytes);
+    }
+
+    /// # Open PNG
+    /// Decodes the PNG file at `path`.
+    pub fn open_png<P: AsRef<Path>>(path: P) -> Result<Image, Error> {
+        return Image::decode_png(&std::fs::read(path)?);
+    }
+}
+
+fn straight<T: Copy>(bitmap: &Bitmap<T>, f: impl Fn(T) -> [f32; 4]) -> Result<(UVec2, Vec<Rgba<f32>>), Error> {
+    let size = size(bitmap.width, bitmap.height)?;
+    return Ok((size, bitmap.buffer.iter().map(|p| Rgba::from(f(*p))).collect()));
+}
+
+// Whether the file has a tRNS chunk. It has to come before
+// the image data, and `Decoder::inspect` only reads the
+// header, so this walks the chunks up to the first IDAT.
+fn has_transparency(bytes: &[u8]) -> bool {
+    // Skips the signature.
+    let mut offset = 8;
+    while let Some(header) = bytes.get(offset..offset + 8) {
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        match &header[4..] {
+            b"tRNS" => return true,
+            b"IDAT" | b"IEND" => return false,
+            _ => {}
+        }
+        // The type, length and CRC take 12 bytes.
+        offset = match offset.checked_add(length).and_then(|o| o.checked_add(12)) {
+            Some(offset) => offset,
+            None => return false,
+        };
+    }
+    return false;
+}
@@ -0,0 +1,286 @@
+//! # Netpbm
+//! Encoding and decoding of [`Image`]s to and from
+//! the Netpbm formats: PBM, PGM, PPM and PAM.
+//!
+//! Every variant is read, plain (ASCII) ones too;
+//! PGM, PPM and PAM are written in binary form.
+
+use std::{fs::File, io::{BufWriter, Read, Write}, path::Path};
+
+use rgb::Rgba;
+
+use crate::{layer::Image, size::pixel_count};
+use super::{luma, quantize, size, BitDepth, Error};
+
+/// # Pnm format
+/// The Netpbm format an image is written as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PnmFormat {
+    /// Greyscale, `P5`. Alpha is dropped.
+    Pgm,
+    /// RGB, `P6`. Alpha is dropped.
+    Ppm,
+    /// RGB with alpha, `P7` with the `RGB_ALPHA`
+    /// tuple type.
+    #[default]
+    Pam,
+}
+
+impl Image {
+    /// # Encode PNM
+    /// Encodes the image to a Netpbm format, in memory.
+    /// Formats without alpha keep the straight colors,
+    /// they aren't composited on any background.
+    pub fn encode_pnm(&self, format: PnmFormat, depth: BitDepth) -> Result<Vec<u8>, Error> {
+        let (width, height) = (self.size.x as usize, self.size.y as usize);
+        if self.pixels.len() != width * height {
+            return Err(Error::Dimensions);
+        }
+
+        let max = depth.max();
+        let mut out = match format {
+            PnmFormat::Pgm => format!("P5\n{width} {height}\n{max}\n").into_bytes(),
+            PnmFormat::Ppm => format!("P6\n{width} {height}\n{max}\n").into_bytes(),
+            PnmFormat::Pam => format!(
+                "P7\nWIDTH {width}\nHEIGHT {height}\nDEPTH 4\nMAXVAL {max}\nTUPLTYPE RGB_ALPHA\nENDHDR\n"
+            ).into_bytes(),
+        };
+        for pixel in self.to_straight() {
+            let channels = match format {
+                PnmFormat::Pgm => vec![luma(pixel)],
+                PnmFormat::Ppm => vec![pixel.r, pixel.g, pixel.b],
+                PnmFormat::Pam => vec![pixel.r, pixel.g, pixel.b, pixel.a],
+            };
+            for c in channels {
+                let c = quantize(c, max as f32) as u16;
+                match depth {
+                    BitDepth::Eight => out.push(c as u8),
+                    BitDepth::Sixteen => out.extend(c.to_be_bytes()),
+                }
+            }
+        }
+        return Ok(out);
+    }
+
+    /// # Write PNM
+    /// Encodes the image to a Netpbm format into `writer`.
+    pub fn write_pnm<W: Write>(&self, mut writer: W, format: PnmFormat, depth: BitDepth) -> Result<(), Error> {
+        writer.write_all(&self.encode_pnm(format, depth)?)?;
+        return Ok(());
+    }
+
+    /// # Save PNM
+    /// Encodes the image to a Netpbm file at `path`.
+    pub fn save_pnm<P: AsRef<Path>>(&self, path: P, format: PnmFormat, depth: BitDepth) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_pnm(&mut writer, format, depth)?;
+        writer.flush()?;
+        return Ok(());
+    }
+
+    /// # Decode PNM
+    /// Decodes any Netpbm image held in memory, from
+    /// `P1` to `P7`. Samples are normalized by the
+    /// maximum value of the file, and the image is
+    /// tagged as sRGB.
+    pub fn decode_pnm(bytes: &[u8]) -> Result<Image, Error> {
+        if bytes.len() < 2 || bytes[0] != b'P' {
+            return Err(Error::Format("not a netpbm file"));
+        }
+        let kind = bytes[1];
+        let mut header = Header { bytes, pos: 2 };
+
+        let (width, height, channels, max) = match kind {
+            b'1' | b'4' => (header.number()?, header.number()?, 1, 1),
+            b'2' | b'5' => (header.number()?, header.number()?, 1, header.number()?),
+            b'3' | b'6' => (header.number()?, header.number()?, 3, header.number()?),
+            b'7' => header.pam()?,
+            _ => return Err(Error::Format("unknown netpbm variant")),
+        };
+        if !(1..=4).contains(&channels) || max == 0 || max > 65535 {
+            return Err(Error::Format("unsupported netpbm header"));
+        }
+        let size = size(width, height)?;
+        let count = pixel_count(size);
+        let total = count.checked_mul(channels).ok_or(Error::Dimensions)?;
+
+        let samples = match kind {
+            // Bitmaps store black as 1.
+            b'1' => header.bits(count)?.into_iter().map(|bit| 1 - bit).collect(),
+            b'4' => packed_bits(header.binary()?, width, height)?,
+            b'2' | b'3' => header.plain(total, max)?,
+            _ => binary_samples(header.binary()?, total, max)?,
+        };
+
+        let max = max as f32;
+        let pixels = samples.chunks_exact(channels).map(|s| {
+            let s = |i: usize| s[i] as f32 / max;
+            return match channels {
+                1 => Rgba::from([s(0), s(0), s(0), 1.0]),
+                2 => Rgba::from([s(0), s(0), s(0), s(1)]),
+                3 => Rgba::from([s(0), s(1), s(2), 1.0]),
+                _ => Rgba::from([s(0), s(1), s(2), s(3)]),
+            };
+        }).collect();
+        return Ok(Image::from_straight(size, pixels));
+    }
+
+    /// # Read PNM
+    /// Decodes a Netpbm image read from `reader`.
+    pub fn read_pnm<R: Read>(mut reader: R) -> Result<Image, Error> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        return Image::decode_pnm(&bytes);
+    }
+
+    /// # Open PNM
+    /// Decodes the Netpbm file at `path`.
+    pub fn open_pnm<P: AsRef<Path>>(path: P) -> Result<Image, Error> {
+        return Image::decode_pnm(&std::fs::read(path)?);
+    }
+}
+
+// Reads the whitespace separated tokens of a header,
+// skipping comments.
+struct Header<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Header<'a> {
+    fn skip_space(&mut self) {
+        while let Some(&c) = self.bytes.get(self.pos) {
+            if c == b'#' {
+                while self.bytes.get(self.pos).is_some_and(|&c| c != b'\n') {
+                    self.pos += 1;
+                }
+            } else if c.is_ascii_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn token(&mut self) -> Result<&'a [u8], Error> {
+        self.skip_space();
+        let start = self.pos;
+        while self.bytes.get(self.pos).is_some_and(|c| !c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(Error::Format("truncated netpbm header"));
+        }
+        return Ok(&self.bytes[start..self.pos]);
+    }
+
+    fn number(&mut self) -> Result<usize, Error> {
+        let token = self.token()?;
+        return std::str::from_utf8(token).ok()
+            .and_then(|t| t.parse().ok())
+            .ok_or(Error::Format("invalid number in netpbm header"));
+    }
+
+    // Parses the header of a PAM, returning its width,
+    // height, depth and maximum value.
+    fn pam(&mut self) -> Result<(usize, usize, usize, usize), Error> {
+        let (mut width, mut height, mut depth, mut max) = (None, None, None, None);
+        loop {
+            match self.token()? {
+                b"WIDTH" => width = Some(self.number()?),
+                b"HEIGHT" => height = Some(self.number()?),
+                b"DEPTH" => depth = Some(self.number()?),
+                b"MAXVAL" => max = Some(self.number()?),
+                b"ENDHDR" => break,
+                // The depth already tells the channels apart,
+                // so the tuple type can be ignored.
+                b"TUPLTYPE" => {
+                    while self.bytes.get(self.pos).is_some_and(|&c| c != b'\n') {
+                        self.pos += 1;
+                    }
+                }
+                _ => return Err(Error::Format("unknown pam header field")),
+            }
+        }
+        return match (width, height, depth, max) {
+            (Some(width), Some(height), Some(depth), Some(max)) => Ok((width, height, depth, max)),
+            _ => Err(Error::Format("incomplete pam header")),
+        };
+    }
+
+    // The raster of binary formats starts after a single
+    // whitespace character.
+    fn binary(&self) -> Result<&'a [u8], Error> {
+        return self.bytes.get(self.pos + 1..).ok_or(Error::Format("missing netpbm raster"));
+    }
+
+    // Each plain sample takes at least a byte, which bounds
+    // what a header can make us allocate.
+    fn remaining(&self, count: usize) -> Result<(), Error> {
+        if count > self.bytes.len().saturating_sub(self.pos) {
+            return Err(Error::Format("truncated netpbm raster"));
+        }
+        return Ok(());
+    }
+
+    fn plain(&mut self, count: usize, max: usize) -> Result<Vec<u16>, Error> {
+        self.remaining(count)?;
+        return (0..count).map(|_| {
+            let n = self.number()?;
+            if n > max {
+                return Err(Error::Format("netpbm sample larger than the maximum value"));
+            }
+            return Ok(n as u16);
+        }).collect();
+    }
+
+    // Plain bitmaps don't need whitespace between pixels.
+    fn bits(&mut self, count: usize) -> Result<Vec<u16>, Error> {
+        self.remaining(count)?;
+        let mut bits = Vec::with_capacity(count);
+        while bits.len() < count {
+            self.skip_space();
+            match self.bytes.get(self.pos) {
+                Some(b'0') => bits.push(0),
+                Some(b'1') => bits.push(1),
+                _ => return Err(Error::Format("invalid plain pbm raster")),
+            }
+            self.pos += 1;
+        }
+        return Ok(bits);
+    }
+}
+
+// Rows of packed bitmaps are padded to a whole byte.
+fn packed_bits(raster: &[u8], width: usize, height: usize) -> Result<Vec<u16>, Error> {
+    let row = width.div_ceil(8);
+    if raster.len() < row.checked_mul(height).ok_or(Error::Dimensions)? {
+        return Err(Error::Format("truncated pbm raster"));
+    }
+    let mut samples = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let bit = (raster[y * row + x / 8] >> (7 - x % 8)) & 1;
+            samples.push(1 - bit as u16);
+        }
+    }
+    return Ok(samples);
+}
+
+// Samples take two big endian bytes when the maximum
+// value doesn't fit in one.
+fn binary_samples(raster: &[u8], count: usize, max: usize) -> Result<Vec<u16>, Error> {
+    let size = if max < 256 { 1 } else { 2 };
+    if raster.len() < count.checked_mul(size).ok_or(Error::Dimensions)? {
+        return Err(Error::Format("truncated netpbm raster"));
+    }
+    let samples: Vec<u16> = if size == 1 {
+        raster[..count].iter().map(|&s| s as u16).collect()
+    } else {
+        raster[..count * 2].chunks_exact(2).map(|s| u16::from_be_bytes([s[0], s[1]])).collect()
+    };
+    if samples.iter().any(|&s| s as usize > max) {
+        return Err(Error::Format("netpbm sample larger than the maximum value"));
+    }
+    return Ok(samples);
+}
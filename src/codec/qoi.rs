@@ -0,0 +1,190 @@
+//! # QOI
+//! Encoding and decoding of [`Image`]s to and from
+//! the Quite OK Image format, following the 1.0
+//! specification.
+
+use std::{fs::File, io::{BufWriter, Read, Write}, path::Path};
+
+use rgb::Rgba;
+
+use crate::{color::ColorSpace, layer::Image, size::pixel_count};
+use super::{quantize, size, Error};
+
+const MAGIC: &[u8; 4] = b"qoif";
+const END: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+const OP_INDEX: u8 = 0x00;
+const OP_DIFF: u8 = 0x40;
+const OP_LUMA: u8 = 0x80;
+const OP_RUN: u8 = 0xc0;
+const OP_RGB: u8 = 0xfe;
+const OP_RGBA: u8 = 0xff;
+const MASK: u8 = 0xc0;
+
+fn hash(px: [u8; 4]) -> usize {
+    let [r, g, b, a] = px.map(|c| c as usize);
+    return (r * 3 + g * 5 + b * 7 + a * 11) % 64;
+}
+
+impl Image {
+    /// # Encode QOI
+    /// Encodes the image to QOI, in memory. Images
+    /// tagged with a linear color space are marked
+    /// as such in the header.
+    pub fn encode_qoi(&self) -> Result<Vec<u8>, Error> {
+        let (width, height) = (self.size.x as usize, self.size.y as usize);
+        if self.pixels.len() != width * height || width > u32::MAX as usize || height > u32::MAX as usize {
+            return Err(Error::Dimensions);
+        }
+        let pixels: Vec<[u8; 4]> = self.to_straight().iter()
+            .map(|p| [p.r, p.g, p.b, p.a].map(|c| quantize(c, 255.0) as u8))
+            .collect();
+        let opaque = pixels.iter().all(|p| p[3] == 255);
+
+        let mut out = Vec::with_capacity(14 + pixels.len() * 2 + END.len());
+        out.extend(MAGIC);
+        out.extend((width as u32).to_be_bytes());
+        out.extend((height as u32).to_be_bytes());
+        out.push(if opaque { 3 } else { 4 });
+        out.push(if self.color_space.is_linear() { 1 } else { 0 });
+
+        let mut index = [[0u8; 4]; 64];
+        let mut prev = [0, 0, 0, 255];
+        let mut run = 0u8;
+        for (i, &px) in pixels.iter().enumerate() {
+            if px == prev {
+                run += 1;
+                if run == 62 || i == pixels.len() - 1 {
+                    out.push(OP_RUN | (run - 1));
+                    run = 0;
+                }
+                continue;
+            }
+            if run > 0 {
+                out.push(OP_RUN | (run - 1));
+                run = 0;
+            }
+
+            let h = hash(px);
+            if index[h] == px {
+                out.push(OP_INDEX | h as u8);
+            } else {
+                index[h] = px;
+                if px[3] != prev[3] {
+                    out.extend([OP_RGBA, px[0], px[1], px[2], px[3]]);
+                } else {
+                    let dr = px[0].wrapping_sub(prev[0]) as i8;
+                    let dg = px[1].wrapping_sub(prev[1]) as i8;
+                    let db = px[2].wrapping_sub(prev[2]) as i8;
+                    let dr_dg = dr.wrapping_sub(dg);
+                    let db_dg = db.wrapping_sub(dg);
+                    if (-2..=1).contains(&dr) && (-2..=1).contains(&dg) && (-2..=1).contains(&db) {
+                        out.push(OP_DIFF | ((dr + 2) as u8) << 4 | ((dg + 2) as u8) << 2 | (db + 2) as u8);
+                    } else if (-8..=7).contains(&dr_dg) && (-32..=31).contains(&dg) && (-8..=7).contains(&db_dg) {
+                        out.push(OP_LUMA | (dg + 32) as u8);
+                        out.push(((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8);
+                    } else {
+                        out.extend([OP_RGB, px[0], px[1], px[2]]);
+                    }
+                }
+            }
+            prev = px;
+        }
+        out.extend(END);
+        return Ok(out);
+    }
+
+    /// # Write QOI
+    /// Encodes the image to QOI into `writer`.
+    pub fn write_qoi<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+        writer.write_all(&self.encode_qoi()?)?;
+        return Ok(());
+    }
+
+    /// # Save QOI
+    /// Encodes the image to a QOI file at `path`.
+    pub fn save_qoi<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_qoi(&mut writer)?;
+        writer.flush()?;
+        return Ok(());
+    }
+
+    /// # Decode QOI
+    /// Decodes a QOI image held in memory. The image
+    /// is tagged as linear sRGB if the header says so,
+    /// as sRGB otherwise.
+    pub fn decode_qoi(bytes: &[u8]) -> Result<Image, Error> {
+        if bytes.len() < 14 || &bytes[..4] != MAGIC {
+            return Err(Error::Format("not a qoi file"));
+        }
+        let width = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+        let height = u32::from_be_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize;
+        let size = size(width, height)?;
+        let count = pixel_count(size);
+        let linear = bytes[13] == 1;
+
+        let data = &bytes[14..];
+        let mut pos = 0;
+        let mut next = || -> Result<u8, Error> {
+            let byte = *data.get(pos).ok_or(Error::Format("truncated qoi data"))?;
+            pos += 1;
+            return Ok(byte);
+        };
+
+        // A single byte gives at most a run of 62 pixels, so
+        // headers claiming more than the data can hold don't
+        // get to reserve that much.
+        let mut pixels = Vec::with_capacity(count.min(data.len().saturating_mul(62)));
+        let mut index = [[0u8; 4]; 64];
+        let mut px = [0, 0, 0, 255];
+        while pixels.len() < count {
+            let op = next()?;
+            let mut run = 1;
+            if op == OP_RGB {
+                px = [next()?, next()?, next()?, px[3]];
+            } else if op == OP_RGBA {
+                px = [next()?, next()?, next()?, next()?];
+            } else {
+                match op & MASK {
+                    OP_INDEX => px = index[op as usize],
+                    OP_DIFF => {
+                        px[0] = px[0].wrapping_add((op >> 4) & 3).wrapping_sub(2);
+                        px[1] = px[1].wrapping_add((op >> 2) & 3).wrapping_sub(2);
+                        px[2] = px[2].wrapping_add(op & 3).wrapping_sub(2);
+                    }
+                    OP_LUMA => {
+                        let second = next()?;
+                        let dg = (op & 0x3f).wrapping_sub(32);
+                        px[0] = px[0].wrapping_add(dg).wrapping_add(second >> 4).wrapping_sub(8);
+                        px[1] = px[1].wrapping_add(dg);
+                        px[2] = px[2].wrapping_add(dg).wrapping_add(second & 0x0f).wrapping_sub(8);
+                    }
+                    _ => run = (op & 0x3f) as usize + 1,
+                }
+            }
+            index[hash(px)] = px;
+            for _ in 0..run.min(count - pixels.len()) {
+                pixels.push(Rgba::from(px.map(|c| c as f32 / 255.0)));
+            }
+        }
+
+        let mut image = Image::from_straight(size, pixels);
+        image.color_space = if linear { ColorSpace::LinearSrgb } else { ColorSpace::Srgb };
+        return Ok(image);
+    }
+
+    /// # Read QOI
+    /// Decodes a QOI image read from `reader`.
+    pub fn read_qoi<R: Read>(mut reader: R) -> Result<Image, Error> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        return Image::decode_qoi(&bytes);
+    }
+
+    /// # Open QOI
+    /// Decodes the QOI file at `path`.
+    pub fn open_qoi<P: AsRef<Path>>(path: P) -> Result<Image, Error> {
+        return Image::decode_qoi(&std::fs::read(path)?);
+    }
+}
@@ -0,0 +1,162 @@
+//! # Raw
+//! Reading and writing [`Image`]s from and to raw,
+//! interleaved RGBA buffers, for handing pixels to
+//! and from other libraries without any encoding.
+
+use rgb::Rgba;
+
+use crate::{color::{premultiply, unpremultiply}, layer::Image};
+use super::{quantize, size, Error};
+
+/// # Raw format
+/// The type of each channel of a raw buffer.
+/// Multi-byte channels are in native byte order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RawFormat {
+    #[default]
+    Rgba8,
+    Rgba16,
+    RgbaF32,
+}
+
+impl RawFormat {
+    /// The size of a pixel, in bytes.
+    pub fn pixel_size(&self) -> usize {
+        return match self {
+            RawFormat::Rgba8 => 4,
+            RawFormat::Rgba16 => 8,
+            RawFormat::RgbaF32 => 16,
+        };
+    }
+
+    fn read(&self, bytes: &[u8]) -> Rgba<f32> {
+        let c: [f32; 4] = match self {
+            RawFormat::Rgba8 => std::array::from_fn(|i| bytes[i] as f32 / 255.0),
+            RawFormat::Rgba16 => std::array::from_fn(|i| {
+                return u16::from_ne_bytes([bytes[i * 2], bytes[i * 2 + 1]]) as f32 / 65535.0;
+            }),
+            RawFormat::RgbaF32 => std::array::from_fn(|i| {
+                return f32::from_ne_bytes([bytes[i * 4], bytes[i * 4 + 1], bytes[i * 4 + 2], bytes[i * 4 + 3]]);
+            }),
+        };
+        return Rgba::from(c);
+    }
+
+    fn write(&self, pixel: Rgba<f32>, out: &mut [u8]) {
+        let c = [pixel.r, pixel.g, pixel.b, pixel.a];
+        for (i, c) in c.into_iter().enumerate() {
+            match self {
+                RawFormat::Rgba8 => out[i] = quantize(c, 255.0) as u8,
+                RawFormat::Rgba16 => out[i * 2..i * 2 + 2].copy_from_slice(&(quantize(c, 65535.0) as u16).to_ne_bytes()),
+                RawFormat::RgbaF32 => out[i * 4..i * 4 + 4].copy_from_slice(&c.to_ne_bytes()),
+            }
+        }
+    }
+}
+
+/// # Raw layout
+/// How pixels are laid out in a raw buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawLayout {
+    pub format: RawFormat,
+    /// The distance between the start of two rows,
+    /// in bytes. It can be larger than a row, e.g.
+    /// to keep rows aligned.
+    pub stride: usize,
+    /// Whether the colors are premultiplied by alpha.
+    pub premultiplied: bool,
+}
+
+impl RawLayout {
+    /// Creates a layout for straight pixels, with rows
+    /// `width` pixels long packed one after the other.
+    pub fn new(format: RawFormat, width: usize) -> RawLayout {
+        return RawLayout {
+            format,
+            stride: width * format.pixel_size(),
+            premultiplied: false,
+        };
+    }
+
+    pub fn set_stride(&mut self, stride: usize) {
+        self.stride = stride;
+    }
+
+    pub fn set_premultiplied(&mut self, premultiplied: bool) {
+        self.premultiplied = premultiplied;
+    }
+
+    // Checks that a buffer of `len` bytes can hold an image
+    // of the given size.
+    fn check(&self, width: usize, height: usize, len: usize) -> Result<(), Error> {
+        let row = width.checked_mul(self.format.pixel_size()).ok_or(Error::Dimensions)?;
+        if self.stride < row {
+            return Err(Error::Dimensions);
+        }
+        let needed = match height {
+            0 => 0,
+            _ => self.stride.checked_mul(height - 1).and_then(|n| n.checked_add(row)).ok_or(Error::Dimensions)?,
+        };
+        if len < needed {
+            return Err(Error::Dimensions);
+        }
+        return Ok(());
+    }
+}
+
+impl Image {
+    /// # From raw
+    /// Creates an image out of a raw buffer, tagged
+    /// as sRGB. The padding between rows is ignored.
+    pub fn from_raw(data: &[u8], width: usize, height: usize, layout: RawLayout) -> Result<Image, Error> {
+        let image_size = size(width, height)?;
+        layout.check(width, height, data.len())?;
+        let pixel_size = layout.format.pixel_size();
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let row = &data[y * layout.stride..y * layout.stride + width * pixel_size];
+            pixels.extend(row.chunks_exact(pixel_size).map(|bytes| {
+                let pixel = layout.format.read(bytes);
+                return if layout.premultiplied { pixel } else { premultiply(pixel) };
+            }));
+        }
+        return Ok(Image::from_pixels(image_size, pixels));
+    }
+
+    /// # To raw
+    /// Returns the pixels of the image as a raw buffer.
+    /// Padding between rows is filled with zeros.
+    pub fn to_raw(&self, layout: RawLayout) -> Result<Vec<u8>, Error> {
+        let (width, height) = (self.size.x as usize, self.size.y as usize);
+        if self.pixels.len() != width * height {
+            return Err(Error::Dimensions);
+        }
+        let len = layout.stride.checked_mul(height).ok_or(Error::Dimensions)?;
+        // Also rejects strides shorter than a row.
+        layout.check(width, height, len)?;
+        let mut data = vec![0; len];
+        self.write_raw(&mut data, layout)?;
+        return Ok(data);
+    }
+
+    /// # Write raw
+    /// Writes the pixels of the image into an existing
+    /// raw buffer, leaving the padding between rows
+    /// untouched.
+    pub fn write_raw(&self, data: &mut [u8], layout: RawLayout) -> Result<(), Error> {
+        let (width, height) = (self.size.x as usize, self.size.y as usize);
+        if self.pixels.len() != width * height {
+            return Err(Error::Dimensions);
+        }
+        layout.check(width, height, data.len())?;
+        let size = layout.format.pixel_size();
+        for (y, pixels) in self.pixels.chunks_exact(width.max(1)).enumerate().take(height) {
+            let row = &mut data[y * layout.stride..y * layout.stride + width * size];
+            for (out, pixel) in row.chunks_exact_mut(size).zip(pixels) {
+                let pixel = if layout.premultiplied { *pixel } else { unpremultiply(*pixel) };
+                layout.format.write(pixel, out);
+            }
+        }
+        return Ok(());
+    }
+}
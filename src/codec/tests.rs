@@ -0,0 +1,309 @@
+use glam::UVec2;
+use rgb::{ComponentMap, Rgba};
+
+use crate::{color::{linear_to_srgb, ColorSpace}, layer::Image};
+use super::{png::{ColorType, PngOptions}, pnm::PnmFormat, raw::{RawFormat, RawLayout}, BitDepth, Error};
+
+const EIGHT: f32 = 0.5 / 255.0 + 1e-6;
+const SIXTEEN: f32 = 0.5 / 65535.0 + 1e-6;
+
+// A 4x3 image whose straight colors fall between the steps
+// of 8 bit samples, so that 16 bit files have to keep them.
+fn sample(grey: bool, alpha: bool) -> Image {
+    let pixels = (0..12u32).map(|i| {
+        let c = |k: u32| ((i * 4099 + k * 12345 + 321) % 65536) as f32 / 65535.0;
+        let a = if alpha { 1.0 - (i % 3) as f32 * 0.25 } else { 1.0 };
+        return if grey { Rgba::from([c(0), c(0), c(0), a]) } else { Rgba::from([c(0), c(1), c(2), a]) };
+    }).collect();
+    return Image::from_straight(UVec2::new(4, 3), pixels);
+}
+
+fn assert_close(a: &Image, b: &Image, tolerance: f32) {
+    assert_eq!(a.size, b.size);
+    assert_pixels_close(&a.to_straight(), &b.to_straight(), tolerance);
+}
+
+fn assert_pixels_close(a: &[Rgba<f32>], b: &[Rgba<f32>], tolerance: f32) {
+    assert_eq!(a.len(), b.len());
+    for (a, b) in a.iter().zip(b) {
+        let error = [a.r - b.r, a.g - b.g, a.b - b.b, a.a - b.a].map(f32::abs);
+        assert!(error.iter().all(|e| *e <= tolerance), "{a:?} != {b:?}");
+    }
+}
+
+fn greys(image: &Image) -> Vec<f32> {
+    return image.pixels.iter().map(|p| p.r).collect();
+}
+
+#[test]
+fn png_encode() {
+    let types = [
+        (ColorType::Rgba, lodepng::ColorType::RGBA, false, true),
+        (ColorType::Rgb, lodepng::ColorType::RGB, false, false),
+        (ColorType::Grey, lodepng::ColorType::GREY, true, false),
+        (ColorType::GreyAlpha, lodepng::ColorType::GREY_ALPHA, true, true),
+    ];
+    for (depth, bits, tolerance) in [(BitDepth::Eight, 8, EIGHT), (BitDepth::Sixteen, 16, SIXTEEN)] {
+        for (color_type, written, grey, alpha) in types {
+            let image = sample(grey, alpha);
+            let bytes = image.encode_png(PngOptions::new(color_type, depth)).unwrap();
+            let mut decoder = lodepng::Decoder::new();
+            decoder.info_raw_mut().colortype = lodepng::ColorType::RGBA;
+            decoder.info_raw_mut().set_bitdepth(16);
+            let lodepng::Image::RGBA16(bitmap) = decoder.decode(&bytes).unwrap() else {
+                panic!("lodepng didn't decode to 16 bit RGBA");
+            };
+            assert_eq!(decoder.info_png().color.colortype, written);
+            assert_eq!(decoder.info_png().color.bitdepth(), bits);
+            // Colors are written straight.
+            let n = |c: u16| c as f32 / 65535.0;
+            let pixels = bitmap.buffer.iter().map(|p| Rgba::from([n(p.r), n(p.g), n(p.b), n(p.a)])).collect::<Vec<_>>();
+            assert_pixels_close(&image.to_straight(), &pixels, tolerance);
+        }
+    }
+}
+
+#[test]
+fn png_round_trip() {
+    let types = [
+        (ColorType::Rgba, false, true),
+        (ColorType::Rgb, false, false),
+        (ColorType::Grey, true, false),
+        (ColorType::GreyAlpha, true, true),
+    ];
+    for (depth, tolerance) in [(BitDepth::Eight, EIGHT), (BitDepth::Sixteen, SIXTEEN)] {
+        for (color_type, grey, alpha) in types {
+            let image = sample(grey, alpha);
+            let bytes = image.encode_png(PngOptions::new(color_type, depth)).unwrap();
+            let decoded = Image::decode_png(&bytes).unwrap();
+            assert_close(&image, &decoded, tolerance);
+            assert_eq!(decoded.color_space, ColorSpace::Srgb);
+        }
+    }
+}
+
+#[test]
+fn png_gamma() {
+    // Files with only a gAMA chunk of 1 hold linear samples,
+    // other gammas are re-encoded as sRGB.
+    let mut encoder = lodepng::Encoder::new();
+    encoder.info_png_mut().gama_defined = 1;
+    encoder.info_png_mut().gama_gamma = 100000;
+    let bytes = encoder.encode(&[255u8, 128, 0, 255], 1, 1).unwrap();
+    let decoded = Image::decode_png(&bytes).unwrap();
+    assert_eq!(decoded.color_space, ColorSpace::LinearSrgb);
+    assert_pixels_close(&decoded.to_straight(), &[Rgba::from([1.0, 128.0 / 255.0, 0.0, 1.0])], 1e-6);
+
+    encoder.info_png_mut().gama_gamma = 45455;
+    let decoded = Image::decode_png(&encoder.encode(&[255u8, 128, 0, 255], 1, 1).unwrap()).unwrap();
+    assert_eq!(decoded.color_space, ColorSpace::Srgb);
+    let expected = linear_to_srgb((128.0f32 / 255.0).powf(100000.0 / 45455.0));
+    assert_pixels_close(&decoded.to_straight(), &[Rgba::from([1.0, expected, 0.0, 1.0])], 1e-5);
+}
+
+#[test]
+fn png_wide_gamut() {
+    let spaces = [(ColorSpace::DisplayP3, ColorSpace::Srgb), (ColorSpace::LinearRec2020, ColorSpace::Srgb), (ColorSpace::LinearSrgb, ColorSpace::Srgb)];
+    for (space, written) in spaces {
+        let mut image = sample(false, true);
+        image.color_space = space;
+        let decoded = Image::decode_png(&image.encode_png(PngOptions::default()).unwrap()).unwrap();
+        assert_eq!(decoded.color_space, written);
+        // Colors outside of sRGB are clipped.
+        image.convert(written);
+        let clipped = image.to_straight().into_iter().map(|p| p.map(|c| c.clamp(0.0, 1.0))).collect();
+        assert_close(&Image::from_straight(image.size, clipped), &decoded, EIGHT);
+    }
+}
+
+#[test]
+fn png_transparency() {
+    // One grey level or color is transparent. 16 bit
+    // samples are big endian, as lodepng wants them.
+    let cases = [
+        (lodepng::ColorType::GREY, 8, vec![0u8, 200, 100, 200], 1),
+        (lodepng::ColorType::GREY, 16, vec![0u8, 0, 200, 1, 100, 0, 200, 1], 1),
+        (lodepng::ColorType::RGB, 8, vec![0u8, 0, 0, 200, 10, 20, 100, 10, 20, 200, 10, 20], 1),
+        (lodepng::ColorType::RGB, 16, (0..24).map(|i| if i / 6 % 2 == 1 { 0 } else if i % 6 == 0 { 200 } else { 1 }).collect(), 0),
+    ];
+    for (colortype, depth, samples, keyed) in cases {
+        let mut encoder = lodepng::Encoder::new();
+        encoder.set_auto_convert(false);
+        encoder.info_raw_mut().colortype = colortype;
+        encoder.info_raw_mut().set_bitdepth(depth);
+        encoder.info_png_mut().color.colortype = colortype;
+        encoder.info_png_mut().color.set_bitdepth(depth);
+        if depth == 8 {
+            encoder.info_png_mut().color.set_key(200, 10, 20);
+        } else {
+            encoder.info_png_mut().color.set_key(200 * 256 + 1, 257, 257);
+        }
+        let decoded = Image::decode_png(&encoder.encode(&samples, 4, 1).unwrap()).unwrap();
+        let alphas = decoded.pixels.iter().map(|p| p.a).collect::<Vec<_>>();
+        let expected = (0..4).map(|i| if i % 2 == keyed { 0.0 } else { 1.0 }).collect::<Vec<_>>();
+        assert_eq!(alphas, expected, "{colortype:?} at {depth} bits");
+    }
+}
+
+#[test]
+fn png_linear() {
+    let mut image = sample(false, true);
+    image.color_space = ColorSpace::LinearSrgb;
+    let mut options = PngOptions::new(ColorType::Rgba, BitDepth::Sixteen);
+    options.set_linear(true);
+    let decoded = Image::decode_png(&image.encode_png(options).unwrap()).unwrap();
+    assert_eq!(decoded.color_space, ColorSpace::LinearSrgb);
+    assert_close(&image, &decoded, SIXTEEN);
+
+    // sRGB images are converted to linear light.
+    let srgb = sample(false, true);
+    let decoded = Image::decode_png(&srgb.encode_png(options).unwrap()).unwrap();
+    assert_eq!(decoded.color_space, ColorSpace::LinearSrgb);
+    let mut expected = srgb.clone();
+    expected.convert(ColorSpace::LinearSrgb);
+    assert_close(&expected, &decoded, SIXTEEN);
+
+    // 8 bit files are always sRGB.
+    options.bit_depth = BitDepth::Eight;
+    let decoded = Image::decode_png(&image.encode_png(options).unwrap()).unwrap();
+    assert_eq!(decoded.color_space, ColorSpace::Srgb);
+    image.convert(ColorSpace::Srgb);
+    assert_close(&image, &decoded, EIGHT);
+}
+
+#[test]
+fn pnm_round_trip() {
+    let formats = [(PnmFormat::Pgm, true, false), (PnmFormat::Ppm, false, false), (PnmFormat::Pam, false, true)];
+    for (depth, tolerance) in [(BitDepth::Eight, EIGHT), (BitDepth::Sixteen, SIXTEEN)] {
+        for (format, grey, alpha) in formats {
+            let image = sample(grey, alpha);
+            let bytes = image.encode_pnm(format, depth).unwrap();
+            assert_close(&image, &Image::decode_pnm(&bytes).unwrap(), tolerance);
+        }
+    }
+}
+
+#[test]
+fn pnm_plain_and_bitmaps() {
+    // Black is 1 in bitmaps.
+    let p1 = Image::decode_pnm(b"P1\n# a comment\n3 2\n0 1 0\n101").unwrap();
+    assert_eq!(p1.size, UVec2::new(3, 2));
+    assert_eq!(greys(&p1), vec![1.0, 0.0, 1.0, 0.0, 1.0, 0.0]);
+
+    // Rows of 10 pixels take two bytes.
+    let mut p4 = b"P4\n10 2\n".to_vec();
+    p4.extend([0b1000_0000, 0b0100_0000, 0b0000_0000, 0b1100_0000]);
+    let p4 = Image::decode_pnm(&p4).unwrap();
+    let mut expected = vec![1.0; 20];
+    for i in [0, 9, 18, 19] {
+        expected[i] = 0.0;
+    }
+    assert_eq!(greys(&p4), expected);
+
+    let p2 = Image::decode_pnm(b"P2 2 1 4\n0 4").unwrap();
+    assert_eq!(greys(&p2), vec![0.0, 1.0]);
+
+    let p3 = Image::decode_pnm(b"P3 2 1 255\n255 0 0  0 0 255").unwrap();
+    assert_eq!(p3.pixels, vec![Rgba::from([1.0, 0.0, 0.0, 1.0]), Rgba::from([0.0, 0.0, 1.0, 1.0])]);
+
+    let p7 = Image::decode_pnm(b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 2\nMAXVAL 255\nTUPLTYPE GRAYSCALE_ALPHA\nENDHDR\n\xff\x80").unwrap();
+    assert_close(&p7, &Image::from_straight(UVec2::ONE, vec![Rgba::from([1.0, 1.0, 1.0, 128.0 / 255.0])]), 1e-6);
+}
+
+#[test]
+fn pnm_malformed() {
+    let cases: [&[u8]; 12] = [
+        b"",
+        b"Q6 1 1 255\n\0\0\0",
+        b"P8 1 1 255\n\0",
+        b"P6 1 1",
+        b"P6 1 1 0\n\0\0\0",
+        b"P6 2 2 255\n\0\0\0",
+        b"P6 one 1 255\n\0\0\0",
+        b"P7\nWIDTH 1\nHEIGHT 1\nENDHDR\n\0",
+        b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 5\nMAXVAL 255\nENDHDR\n\0\0\0\0\0",
+        b"P2 2 2 255\n1 2 3",
+        b"P2 2 1 255\n0 256",
+        b"P5 2 1 100\n\x00\x65",
+    ];
+    for bytes in cases {
+        assert!(matches!(Image::decode_pnm(bytes), Err(Error::Format(_))), "{:?}", String::from_utf8_lossy(bytes));
+    }
+    // Sizes that can't be allocated are rejected before reading
+    // the raster.
+    assert!(matches!(Image::decode_pnm(b"P5 4294967295 4294967295 255\n\0"), Err(Error::Dimensions)));
+    assert!(matches!(Image::decode_pnm(b"P5 0 1 255\n"), Err(Error::Dimensions)));
+    assert!(matches!(Image::decode_pnm(b"P2 60000 60000 255\n1 2"), Err(Error::Format(_))));
+}
+
+#[test]
+fn qoi_round_trip() {
+    for alpha in [false, true] {
+        let image = sample(false, alpha);
+        assert_close(&image, &Image::decode_qoi(&image.encode_qoi().unwrap()).unwrap(), EIGHT);
+    }
+
+    // Long runs are split in runs of 62.
+    let mut image = Image::from_straight(UVec2::new(100, 2), vec![Rgba::from([0.2, 0.4, 0.6, 1.0]); 200]);
+    image.pixels[150] = Rgba::from([0.0, 0.0, 0.0, 0.0]);
+    image.color_space = ColorSpace::LinearSrgb;
+    let decoded = Image::decode_qoi(&image.encode_qoi().unwrap()).unwrap();
+    assert_close(&image, &decoded, EIGHT);
+    assert_eq!(decoded.color_space, ColorSpace::LinearSrgb);
+}
+
+#[test]
+fn qoi_malformed() {
+    let header = |width: u32, height: u32| {
+        let mut bytes = b"qoif".to_vec();
+        bytes.extend(width.to_be_bytes());
+        bytes.extend(height.to_be_bytes());
+        bytes.extend([4, 0]);
+        return bytes;
+    };
+    assert!(matches!(Image::decode_qoi(b"qoif"), Err(Error::Format(_))));
+    let mut magic = header(1, 1);
+    magic[0] = b'Q';
+    assert!(matches!(Image::decode_qoi(&magic), Err(Error::Format(_))));
+    // Missing pixels.
+    let mut truncated = header(2, 2);
+    truncated.extend([0xfe, 255, 0, 0]);
+    assert!(matches!(Image::decode_qoi(&truncated), Err(Error::Format(_))));
+    // A huge size with no data fails without reserving it.
+    assert!(matches!(Image::decode_qoi(&header(u32::MAX, u32::MAX)), Err(Error::Dimensions)));
+    assert!(matches!(Image::decode_qoi(&header(60000, 60000)), Err(Error::Format(_))));
+    assert!(matches!(Image::decode_qoi(&header(0, 4)), Err(Error::Dimensions)));
+}
+
+#[test]
+fn raw_round_trip() {
+    let image = sample(false, true);
+    for (format, tolerance) in [(RawFormat::Rgba8, EIGHT), (RawFormat::Rgba16, SIXTEEN), (RawFormat::RgbaF32, 1e-6)] {
+        for premultiplied in [false, true] {
+            let mut layout = RawLayout::new(format, 4);
+            // Rows padded by 5 bytes.
+            layout.set_stride(4 * format.pixel_size() + 5);
+            layout.set_premultiplied(premultiplied);
+            let data = image.to_raw(layout).unwrap();
+            assert_eq!(data.len(), layout.stride * 3);
+            assert!(data.chunks(layout.stride).all(|row| row[row.len() - 5..] == [0; 5]));
+            let decoded = Image::from_raw(&data, 4, 3, layout).unwrap();
+            if premultiplied {
+                assert_pixels_close(&image.pixels, &decoded.pixels, tolerance);
+            } else {
+                assert_close(&image, &decoded, tolerance);
+            }
+        }
+    }
+
+    // The last row doesn't need its padding.
+    let mut layout = RawLayout::new(RawFormat::Rgba8, 4);
+    layout.set_stride(20);
+    assert!(Image::from_raw(&[0; 56], 4, 3, layout).is_ok());
+    assert!(matches!(Image::from_raw(&[0; 55], 4, 3, layout), Err(Error::Dimensions)));
+    layout.set_stride(12);
+    assert!(matches!(Image::from_raw(&[0; 64], 4, 3, layout), Err(Error::Dimensions)));
+    assert!(matches!(image.to_raw(layout), Err(Error::Dimensions)));
+    layout.set_stride(usize::MAX);
+    assert!(matches!(image.to_raw(layout), Err(Error::Dimensions)));
+}
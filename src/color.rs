@@ -0,0 +1,278 @@
+//! # Color
+//! Conversions between sRGB encoded and linear light
+//! colors, and between color spaces.
+//! 
+//! Colors given by users, and the images handed back
+//! to them, are sRGB encoded. Blending encoded values
+//! darkens mixtures and anti-aliased edges, so scenes
+//! decode colors to linear light before blending them
+//! by default, and encode them again once done. Lone
+//! layers keep blending encoded values unless told
+//! otherwise, so that they paint sRGB images.
+//! 
+//! Colors from wider gamuts, like Display P3, are
+//! converted to sRGB with [`convert`] without being
+//! clamped, so they end up with components outside
+//! of `[0, 1]` and survive until the image is
+//! converted to its final [`ColorSpace`].
+
+use glam::{Mat3, Vec3};
+use rgb::Rgba;
+
+/// # Blend space
+/// Whether colors are blended as linear light or as
+/// sRGB encoded values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlendSpace {
+    /// Blend in linear light, which is physically
+    /// correct. The default of [`crate::scene::Scene`].
+    Linear,
+    /// Blend the encoded values, like most legacy
+    /// renderers and browsers do.
+    #[default]
+    Srgb,
+}
+
+/// # Color space
+/// The color spaces images can be tagged with and
+/// converted between. All of them use the D65
+/// white point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorSpace {
+    /// sRGB encoded, the space of the web and of
+    /// most images.
+    #[default]
+    Srgb,
+    /// sRGB primaries, in linear light.
+    LinearSrgb,
+    /// Display P3 encoded, with the sRGB transfer
+    /// function.
+    DisplayP3,
+    /// Display P3 primaries, in linear light.
+    LinearDisplayP3,
+    /// ITU-R BT.2020 encoded.
+    Rec2020,
+    /// ITU-R BT.2020 primaries, in linear light.
+    LinearRec2020,
+}
+
+impl ColorSpace {
+    /// Whether the components are in linear light.
+    pub fn is_linear(&self) -> bool {
+        return matches!(self, ColorSpace::LinearSrgb | ColorSpace::LinearDisplayP3 | ColorSpace::LinearRec2020);
+    }
+
+    /// Returns the linear space with the same primaries.
+    pub fn linear(&self) -> ColorSpace {
+        return match self {
+            ColorSpace::Srgb | ColorSpace::LinearSrgb => ColorSpace::LinearSrgb,
+            ColorSpace::DisplayP3 | ColorSpace::LinearDisplayP3 => ColorSpace::LinearDisplayP3,
+            ColorSpace::Rec2020 | ColorSpace::LinearRec2020 => ColorSpace::LinearRec2020,
+        };
+    }
+
+    /// # To XYZ
+    /// The matrix taking linear components in this
+    /// space to CIE XYZ.
+    pub fn to_xyz(&self) -> Mat3 {
+        let rows = match self.linear() {
+            ColorSpace::LinearDisplayP3 => [
+                0.48657095, 0.26566769, 0.19821729,
+                0.22897456, 0.69173852, 0.07928691,
+                0.0,        0.04511338, 1.04394437,
+            ],
+            ColorSpace::LinearRec2020 => [
+                0.63695805, 0.14461690, 0.16888098,
+                0.26270021, 0.67799807, 0.05930172,
+                0.0,        0.02807269, 1.06098506,
+            ],
+            _ => [
+                0.41239080, 0.35758434, 0.18048079,
+                0.21263901, 0.71516868, 0.07219232,
+                0.01933082, 0.11919478, 0.95053215,
+            ],
+        };
+        return Mat3::from_cols_array(&rows).transpose();
+    }
+
+    /// Decodes a single component to linear light.
+    pub fn to_linear(&self, c: f32) -> f32 {
+        return match self {
+            ColorSpace::Srgb | ColorSpace::DisplayP3 => srgb_to_linear(c),
+            ColorSpace::Rec2020 => rec2020_to_linear(c),
+            _ => c,
+        };
+    }
+
+    /// Encodes a single linear light component.
+    pub fn from_linear(&self, c: f32) -> f32 {
+        return match self {
+            ColorSpace::Srgb | ColorSpace::DisplayP3 => linear_to_srgb(c),
+            ColorSpace::Rec2020 => linear_to_rec2020(c),
+            _ => c,
+        };
+    }
+}
+
+/// # Convert
+/// Converts the color of a straight (non premultiplied)
+/// pixel from one space to another, leaving alpha
+/// untouched. Colors outside of the gamut of `to`
+/// are not clamped.
+pub fn convert(pixel: Rgba<f32>, from: ColorSpace, to: ColorSpace) -> Rgba<f32> {
+    if from == to { return pixel; }
+    let mut c = Vec3::new(from.to_linear(pixel.r), from.to_linear(pixel.g), from.to_linear(pixel.b));
+    if from.linear() != to.linear() {
+        c = to.to_xyz().inverse() * from.to_xyz() * c;
+    }
+    return Rgba::from([to.from_linear(c.x), to.from_linear(c.y), to.from_linear(c.z), pixel.a]);
+}
+
+/// # sRGB to linear
+/// Decodes a single sRGB encoded component.
+/// Negative values are mirrored, as in extended sRGB.
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c < 0.0 { return -srgb_to_linear(-c); }
+    if c <= 0.04045 {
+        return c / 12.92;
+    }
+    return ((c + 0.055) / 1.055).powf(2.4);
+}
+
+/// # Linear to sRGB
+/// Encodes a single linear light component.
+/// Negative values are mirrored, as in extended sRGB.
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c < 0.0 { return -linear_to_srgb(-c); }
+    if c <= 0.0031308 {
+        return c * 12.92;
+    }
+    return 1.055 * c.powf(1.0 / 2.4) - 0.055;
+}
+
+// The BT.2020 transfer function.
+const REC2020_ALPHA: f32 = 1.0992968;
+const REC2020_BETA: f32 = 0.01805397;
+
+fn rec2020_to_linear(c: f32) -> f32 {
+    if c < 0.0 { return -rec2020_to_linear(-c); }
+    if c < REC2020_BETA * 4.5 {
+        return c / 4.5;
+    }
+    return ((c + REC2020_ALPHA - 1.0) / REC2020_ALPHA).powf(1.0 / 0.45);
+}
+
+fn linear_to_rec2020(c: f32) -> f32 {
+    if c < 0.0 { return -linear_to_rec2020(-c); }
+    if c < REC2020_BETA {
+        return c * 4.5;
+    }
+    return REC2020_ALPHA * c.powf(0.45) - (REC2020_ALPHA - 1.0);
+}
+
+/// # Decode
+/// Decodes the color of a straight (non premultiplied)
+/// pixel, leaving alpha untouched.
+pub fn decode(pixel: Rgba<f32>) -> Rgba<f32> {
+    return Rgba::from([srgb_to_linear(pixel.r), srgb_to_linear(pixel.g), srgb_to_linear(pixel.b), pixel.a]);
+}
+
+/// # Encode
+/// Encodes the color of a straight (non premultiplied)
+/// pixel, leaving alpha untouched.
+pub fn encode(pixel: Rgba<f32>) -> Rgba<f32> {
+    return Rgba::from([linear_to_srgb(pixel.r), linear_to_srgb(pixel.g), linear_to_srgb(pixel.b), pixel.a]);
+}
+
+/// # Decode premultiplied
+/// Like [`decode`], for premultiplied pixels.
+pub fn decode_premultiplied(pixel: Rgba<f32>) -> Rgba<f32> {
+    return premultiplied(pixel, decode);
+}
+
+/// # Encode premultiplied
+/// Like [`encode`], for premultiplied pixels.
+pub fn encode_premultiplied(pixel: Rgba<f32>) -> Rgba<f32> {
+    return premultiplied(pixel, encode);
+}
+
+// Transfer functions aren't linear, so they have to be applied
+// to the straight color.
+fn premultiplied(pixel: Rgba<f32>, f: fn(Rgba<f32>) -> Rgba<f32>) -> Rgba<f32> {
+    if pixel.a <= 0.0 { return pixel; }
+    return premultiply(f(unpremultiply(pixel)));
+}
+
+/// # Premultiply
+/// Converts a straight pixel to a premultiplied one.
+pub fn premultiply(pixel: Rgba<f32>) -> Rgba<f32> {
+    let a = pixel.a;
+    return Rgba::from([pixel.r * a, pixel.g * a, pixel.b * a, a]);
+}
+
+/// # Unpremultiply
+/// Converts a premultiplied pixel to a straight one.
+/// Fully transparent pixels become transparent black.
+pub fn unpremultiply(pixel: Rgba<f32>) -> Rgba<f32> {
+    let a = pixel.a;
+    if a <= 0.0 { return Rgba::from([0.0, 0.0, 0.0, 0.0]); }
+    return Rgba::from([pixel.r / a, pixel.g / a, pixel.b / a, a]);
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+    use rgb::Rgba;
+
+    use super::{convert, ColorSpace};
+
+    const SPACES: [ColorSpace; 6] = [
+        ColorSpace::Srgb,
+        ColorSpace::LinearSrgb,
+        ColorSpace::DisplayP3,
+        ColorSpace::LinearDisplayP3,
+        ColorSpace::Rec2020,
+        ColorSpace::LinearRec2020,
+    ];
+
+    fn assert_close(a: Rgba<f32>, b: [f32; 4], tolerance: f32) {
+        let error = [a.r - b[0], a.g - b[1], a.b - b[2], a.a - b[3]];
+        assert!(error.iter().all(|e| e.abs() <= tolerance), "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn white_point() {
+        // All spaces share the D65 white, at x = 0.3127
+        // and y = 0.3290.
+        let d65 = Vec3::new(0.3127 / 0.3290, 1.0, (1.0 - 0.3127 - 0.3290) / 0.3290);
+        for space in SPACES {
+            let white = space.to_xyz() * Vec3::ONE;
+            assert!(white.abs_diff_eq(d65, 1e-4), "{space:?}: {white}");
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        // Including colors outside of every gamut.
+        let colors = [[0.0, 0.0, 0.0, 1.0], [1.0, 1.0, 1.0, 0.5], [0.9, 0.3, 0.05, 1.0], [0.02, 0.6, 0.4, 0.25], [1.2, -0.1, 0.5, 1.0]];
+        for from in SPACES {
+            for to in SPACES {
+                for color in colors {
+                    let back = convert(convert(Rgba::from(color), from, to), to, from);
+                    assert_close(back, color, 1e-5);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn primaries() {
+        // Values from the CSS Color 4 conversion code.
+        let red = Rgba::from([1.0, 0.0, 0.0, 1.0]);
+        assert_close(convert(red, ColorSpace::Srgb, ColorSpace::DisplayP3), [0.91749, 0.20029, 0.13856, 1.0], 1e-3);
+        assert_close(convert(red, ColorSpace::Srgb, ColorSpace::LinearRec2020), [0.62740, 0.06910, 0.01639, 1.0], 1e-3);
+        // Display P3 red is outside of sRGB, and isn't clamped.
+        assert_close(convert(red, ColorSpace::DisplayP3, ColorSpace::Srgb), [1.09310, -0.22674, -0.15014, 1.0], 1e-3);
+        assert_close(convert(Rgba::from([0.5, 0.5, 0.5, 1.0]), ColorSpace::Srgb, ColorSpace::LinearSrgb), [0.21404, 0.21404, 0.21404, 1.0], 1e-5);
+    }
+}
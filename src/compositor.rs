@@ -0,0 +1,387 @@
+//! # Compositor
+//! Combines painted [`Image`]s into a single one.
+//! Every operation works on premultiplied colors,
+//! which is what [`Layer::paint`] produces.
+//! 
+//! Compositing follows the W3C Compositing and
+//! Blending spec: the source is first mixed with
+//! the backdrop through a [`BlendMode`], then the
+//! result is combined with a Porter-Duff [`Operator`].
+
+use glam::{UVec2, Vec3};
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator};
+use rgb::Rgba;
+
+use crate::{layer::{Image, Layer, Shader}, size::SizeError};
+
+/// # Operator
+/// The Porter-Duff compositing operators.
+/// In the descriptions below, the source is the
+/// image being added and the destination is what
+/// has already been composited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Operator {
+    /// Neither source nor destination are kept.
+    Clear,
+    /// Only the source is kept.
+    Src,
+    /// Only the destination is kept.
+    Dst,
+    /// The source is placed over the destination.
+    #[default]
+    SrcOver,
+    /// The destination is placed over the source.
+    DstOver,
+    /// The source, where it overlaps the destination.
+    SrcIn,
+    /// The destination, where it overlaps the source.
+    DstIn,
+    /// The source, where it does not overlap the destination.
+    SrcOut,
+    /// The destination, where it does not overlap the source.
+    DstOut,
+    /// The source on top of the destination, only where
+    /// the destination is.
+    SrcAtop,
+    /// The destination on top of the source, only where
+    /// the source is.
+    DstAtop,
+    /// Source and destination, where they don't overlap.
+    Xor,
+    /// The sum of source and destination, clamped to 1.
+    Plus,
+}
+
+impl Operator {
+    /// # Factors
+    /// Returns the Porter-Duff fractions `Fa` and
+    /// `Fb` by which the source and destination
+    /// are multiplied, given their alphas.
+    pub fn factors(&self, src_alpha: f32, dst_alpha: f32) -> (f32, f32) {
+        return match self {
+            Operator::Clear   => (0.0, 0.0),
+            Operator::Src     => (1.0, 0.0),
+            Operator::Dst     => (0.0, 1.0),
+            Operator::SrcOver => (1.0, 1.0 - src_alpha),
+            Operator::DstOver => (1.0 - dst_alpha, 1.0),
+            Operator::SrcIn   => (dst_alpha, 0.0),
+            Operator::DstIn   => (0.0, src_alpha),
+            Operator::SrcOut  => (1.0 - dst_alpha, 0.0),
+            Operator::DstOut  => (0.0, 1.0 - src_alpha),
+            Operator::SrcAtop => (dst_alpha, 1.0 - src_alpha),
+            Operator::DstAtop => (1.0 - dst_alpha, src_alpha),
+            Operator::Xor     => (1.0 - dst_alpha, 1.0 - src_alpha),
+            Operator::Plus    => (1.0, 1.0),
+        };
+    }
+
+    /// # Apply
+    /// Composites two premultiplied pixels.
+    pub fn apply(&self, src: Rgba<f32>, dst: Rgba<f32>) -> Rgba<f32> {
+        let (fa, fb) = self.factors(src.a, dst.a);
+        let out = Rgba::from([
+            src.r * fa + dst.r * fb,
+            src.g * fa + dst.g * fb,
+            src.b * fa + dst.b * fb,
+            src.a * fa + dst.a * fb,
+        ]);
+        if *self == Operator::Plus {
+            return Rgba::from([out.r.min(1.0), out.g.min(1.0), out.b.min(1.0), out.a.min(1.0)]);
+        }
+        return out;
+    }
+}
+
+/// # Blend mode
+/// The W3C blend modes, deciding how the colors of
+/// the source and the backdrop mix where they overlap.
+/// All but the last four are separable, meaning they
+/// work on each channel independently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlendMode {
+    /// The source color replaces the backdrop.
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    ColorDodge,
+    ColorBurn,
+    HardLight,
+    SoftLight,
+    Difference,
+    Exclusion,
+    /// Hue of the source with saturation and
+    /// luminosity of the backdrop.
+    Hue,
+    /// Saturation of the source with hue and
+    /// luminosity of the backdrop.
+    Saturation,
+    /// Hue and saturation of the source with
+    /// the luminosity of the backdrop.
+    Color,
+    /// Luminosity of the source with hue and
+    /// saturation of the backdrop.
+    Luminosity,
+}
+
+impl BlendMode {
+    /// # Mix
+    /// Mixes the straight (non premultiplied) colors
+    /// of the backdrop `cb` and the source `cs`.
+    pub fn mix(&self, cb: Vec3, cs: Vec3) -> Vec3 {
+        let separable = |f: fn(f32, f32) -> f32| Vec3::new(f(cb.x, cs.x), f(cb.y, cs.y), f(cb.z, cs.z));
+        return match self {
+            BlendMode::Normal     => cs,
+            BlendMode::Multiply   => cb * cs,
+            BlendMode::Screen     => cb + cs - cb * cs,
+            BlendMode::Overlay    => separable(|b, s| hard_light(s, b)),
+            BlendMode::Darken     => cb.min(cs),
+            BlendMode::Lighten    => cb.max(cs),
+            BlendMode::ColorDodge => separable(color_dodge),
+            BlendMode::ColorBurn  => separable(color_burn),
+            BlendMode::HardLight  => separable(hard_light),
+            BlendMode::SoftLight  => separable(soft_light),
+            BlendMode::Difference => (cb - cs).abs(),
+            BlendMode::Exclusion  => cb + cs - 2.0 * cb * cs,
+            BlendMode::Hue        => set_lum(set_sat(cs, sat(cb)), lum(cb)),
+            BlendMode::Saturation => set_lum(set_sat(cb, sat(cs)), lum(cb)),
+            BlendMode::Color      => set_lum(cs, lum(cb)),
+            BlendMode::Luminosity => set_lum(cb, lum(cs)),
+        };
+    }
+
+    /// # Apply
+    /// Blends the premultiplied source `src` with the
+    /// premultiplied backdrop `dst`, returning the new
+    /// premultiplied source to be composited.
+    pub fn apply(&self, src: Rgba<f32>, dst: Rgba<f32>) -> Rgba<f32> {
+        if *self == BlendMode::Normal || src.a == 0.0 || dst.a == 0.0 {
+            return src;
+        }
+        let cs = Vec3::new(src.r, src.g, src.b) / src.a;
+        let cb = Vec3::new(dst.r, dst.g, dst.b) / dst.a;
+        // Where the backdrop is transparent the source
+        // is left untouched.
+        let mixed = (1.0 - dst.a) * cs + dst.a * self.mix(cb, cs).clamp(Vec3::ZERO, Vec3::ONE);
+        let out = mixed * src.a;
+        return Rgba::from([out.x, out.y, out.z, src.a]);
+    }
+}
+
+fn hard_light(b: f32, s: f32) -> f32 {
+    if s <= 0.5 {
+        return b * 2.0 * s;
+    } else {
+        let s = 2.0 * s - 1.0;
+        return b + s - b * s;
+    }
+}
+
+fn color_dodge(b: f32, s: f32) -> f32 {
+    if b == 0.0 { return 0.0; }
+    if s >= 1.0 { return 1.0; }
+    return f32::min(1.0, b / (1.0 - s));
+}
+
+fn color_burn(b: f32, s: f32) -> f32 {
+    if b >= 1.0 { return 1.0; }
+    if s <= 0.0 { return 0.0; }
+    return 1.0 - f32::min(1.0, (1.0 - b) / s);
+}
+
+fn soft_light(b: f32, s: f32) -> f32 {
+    if s <= 0.5 {
+        return b - (1.0 - 2.0 * s) * b * (1.0 - b);
+    }
+    let d = if b <= 0.25 { ((16.0 * b - 12.0) * b + 4.0) * b } else { b.sqrt() };
+    return b + (2.0 * s - 1.0) * (d - b);
+}
+
+// Helpers for the non-separable modes, straight out of the spec.
+
+fn lum(c: Vec3) -> f32 {
+    return c.dot(Vec3::new(0.3, 0.59, 0.11));
+}
+
+fn clip_color(c: Vec3) -> Vec3 {
+    let l = lum(c);
+    let n = c.min_element();
+    let x = c.max_element();
+    let mut c = c;
+    if n < 0.0 { c = l + (c - l) * l / (l - n); }
+    if x > 1.0 { c = l + (c - l) * (1.0 - l) / (x - l); }
+    return c;
+}
+
+fn set_lum(c: Vec3, l: f32) -> Vec3 {
+    return clip_color(c + (l - lum(c)));
+}
+
+fn sat(c: Vec3) -> f32 {
+    return c.max_element() - c.min_element();
+}
+
+fn set_sat(c: Vec3, s: f32) -> Vec3 {
+    let max = c.max_element();
+    let min = c.min_element();
+    if max <= min { return Vec3::ZERO; }
+    // Scaling around the minimum keeps the middle component in
+    // proportion, and sends min to 0 and max to `s`.
+    return (c - min) * s / (max - min);
+}
+
+/// # Compositor
+/// Holds a destination [`Image`] onto which other
+/// images and layers are stacked, one at a time.
+#[derive(Debug)]
+pub struct Compositor {
+    target: Image,
+}
+
+impl Compositor {
+    /// Creates a compositor over a fully transparent
+    /// destination. Fails if the size is zero or too
+    /// large.
+    pub fn new(size: UVec2) -> Result<Compositor, SizeError> {
+        return Ok(Compositor { target: Image::transparent(size)? });
+    }
+
+    /// Creates a compositor using an existing image
+    /// as the destination.
+    pub fn from_image(target: Image) -> Compositor {
+        return Compositor { target };
+    }
+
+    /// # Composite
+    /// Combines `src` with the destination using `op`.
+    /// Both images must have the same size.
+    pub fn composite(&mut self, src: &Image, op: Operator) {
+        self.blend(src, BlendMode::Normal, op);
+    }
+
+    /// # Blend
+    /// Mixes `src` with the destination using `mode`,
+    /// then combines them using `op`.
+    /// Both images must have the same size.
+    pub fn blend(&mut self, src: &Image, mode: BlendMode, op: Operator) {
+        assert_eq!(src.size, self.target.size, "Composited images must have the same size!");
+        self.target.pixels.par_iter_mut()
+            .zip(src.pixels.par_iter())
+            .for_each(|(dst, src)| *dst = op.apply(mode.apply(*src, *dst), *dst));
+    }
+
+    /// # Layer
+    /// Paints `layer` and composites the result.
+    pub fn layer<M: Shader + ?Sized>(&mut self, layer: &Layer<M>, op: Operator) {
+        self.composite(&layer.paint(), op);
+    }
+
+    pub fn image(&self) -> &Image {
+        return &self.target;
+    }
+
+    /// Consumes the compositor, returning the
+    /// destination image.
+    pub fn finish(self) -> Image {
+        return self.target;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{UVec2, Vec3};
+    use rgb::Rgba;
+
+    use crate::layer::Image;
+
+    use super::{BlendMode, Compositor, Operator};
+
+    fn assert_close(a: Rgba<f32>, b: [f32; 4]) {
+        let error = [a.r - b[0], a.g - b[1], a.b - b[2], a.a - b[3]];
+        assert!(error.iter().all(|e| e.abs() < 1e-6), "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn porter_duff() {
+        // Red at 60% over blue at 50%, premultiplied.
+        let src = Rgba::from([0.6, 0.0, 0.0, 0.6]);
+        let dst = Rgba::from([0.0, 0.0, 0.5, 0.5]);
+        let expected = [
+            (Operator::Clear, [0.0, 0.0, 0.0, 0.0]),
+            (Operator::Src, [0.6, 0.0, 0.0, 0.6]),
+            (Operator::Dst, [0.0, 0.0, 0.5, 0.5]),
+            (Operator::SrcOver, [0.6, 0.0, 0.2, 0.8]),
+            (Operator::DstOver, [0.3, 0.0, 0.5, 0.8]),
+            (Operator::SrcIn, [0.3, 0.0, 0.0, 0.3]),
+            (Operator::DstIn, [0.0, 0.0, 0.3, 0.3]),
+            (Operator::SrcOut, [0.3, 0.0, 0.0, 0.3]),
+            (Operator::DstOut, [0.0, 0.0, 0.2, 0.2]),
+            (Operator::SrcAtop, [0.3, 0.0, 0.2, 0.5]),
+            (Operator::DstAtop, [0.3, 0.0, 0.3, 0.6]),
+            (Operator::Xor, [0.3, 0.0, 0.2, 0.5]),
+            // Alpha is clamped to 1.
+            (Operator::Plus, [0.6, 0.0, 0.5, 1.0]),
+        ];
+        for (op, expected) in expected {
+            assert_close(op.apply(src, dst), expected);
+
+            let mut compositor = Compositor::from_image(Image::from_pixels(UVec2::ONE, vec![dst]));
+            compositor.composite(&Image::from_pixels(UVec2::ONE, vec![src]), op);
+            assert_close(compositor.finish().pixels[0], expected);
+        }
+    }
+
+    #[test]
+    fn blend_modes() {
+        let cb = Vec3::new(0.2, 0.5, 0.8);
+        let cs = Vec3::new(0.6, 0.3, 0.5);
+        let expected = [
+            (BlendMode::Normal, [0.6, 0.3, 0.5]),
+            (BlendMode::Multiply, [0.12, 0.15, 0.4]),
+            (BlendMode::Screen, [0.68, 0.65, 0.9]),
+            (BlendMode::Overlay, [0.24, 0.3, 0.8]),
+            (BlendMode::Darken, [0.2, 0.3, 0.5]),
+            (BlendMode::Lighten, [0.6, 0.5, 0.8]),
+            (BlendMode::ColorDodge, [0.5, 0.5 / 0.7, 1.0]),
+            (BlendMode::ColorBurn, [0.0, 0.0, 0.6]),
+            (BlendMode::HardLight, [0.36, 0.3, 0.8]),
+            (BlendMode::SoftLight, [0.2496, 0.4, 0.8]),
+            (BlendMode::Difference, [0.4, 0.2, 0.3]),
+            (BlendMode::Exclusion, [0.56, 0.5, 0.5]),
+            // The luminosity of the backdrop is 0.443, the
+            // one of the source 0.412.
+            (BlendMode::Hue, [0.819, 0.219, 0.619]),
+            (BlendMode::Saturation, [0.3215, 0.4715, 0.6215]),
+            (BlendMode::Color, [0.631, 0.331, 0.531]),
+            (BlendMode::Luminosity, [0.169, 0.469, 0.769]),
+        ];
+        for (mode, expected) in expected {
+            let mixed = mode.mix(cb, cs);
+            assert!(mixed.abs_diff_eq(Vec3::from(expected), 1e-5), "{mode:?}: {mixed} != {expected:?}");
+        }
+
+        // Colors pushed out of gamut are clipped towards
+        // their luminosity.
+        let mixed = BlendMode::Luminosity.mix(Vec3::X, Vec3::splat(0.9));
+        assert!(mixed.abs_diff_eq(Vec3::new(1.0, 0.9 - 0.3 / 7.0, 0.9 - 0.3 / 7.0), 1e-5), "{mixed}");
+    }
+
+    #[test]
+    fn blend_partial_alpha() {
+        // Grey 0.6 at 50% multiplied onto grey 0.2 at 50%.
+        // Only half of the source overlaps the backdrop,
+        // so the mix is half the source and half the product.
+        let src = Rgba::from([0.3, 0.3, 0.3, 0.5]);
+        let dst = Rgba::from([0.1, 0.1, 0.1, 0.5]);
+        assert_close(BlendMode::Multiply.apply(src, dst), [0.18, 0.18, 0.18, 0.5]);
+        // Over a transparent backdrop, the source is unchanged.
+        assert_close(BlendMode::Multiply.apply(src, Rgba::from([0.0; 4])), [0.3, 0.3, 0.3, 0.5]);
+
+        let mut compositor = Compositor::from_image(Image::from_pixels(UVec2::ONE, vec![dst]));
+        compositor.blend(&Image::from_pixels(UVec2::ONE, vec![src]), BlendMode::Multiply, Operator::SrcOver);
+        assert_close(compositor.finish().pixels[0], [0.18 + 0.05, 0.18 + 0.05, 0.18 + 0.05, 0.75]);
+    }
+}
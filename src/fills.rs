@@ -5,12 +5,222 @@
 
 use rgb::*;
 use glam::{Vec2, Vec4};
-use crate::layer::Shader;
+use crate::{color::{convert, linear_to_srgb, srgb_to_linear, ColorSpace}, layer::Shader};
 
 pub fn mix(a: Vec4, b: Vec4, t: f32) -> Vec4 {
     return (1.0 - t) * a + b*t;
 }
 
+/// # Interpolation
+/// The color space gradients interpolate their
+/// stops in. Stops are always given, and the
+/// resulting colors returned, as sRGB.
+/// 
+/// Interpolation follows CSS Color 4: colors are
+/// premultiplied by alpha while being mixed, and
+/// the hue of the polar spaces travels along the
+/// chosen [`HueArc`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpolation {
+    /// Interpolate the sRGB encoded components, as
+    /// legacy renderers do.
+    Srgb,
+    /// Interpolate in linear light.
+    #[default]
+    LinearSrgb,
+    /// Interpolate in OKLab, which is perceptually
+    /// uniform and keeps midpoints from going muddy.
+    Oklab,
+    /// Interpolate lightness, chroma and hue in OKLCH.
+    Oklch(HueArc),
+    /// Interpolate hue, saturation and lightness of
+    /// the sRGB encoded color.
+    Hsl(HueArc),
+}
+
+/// # Hue arc
+/// Which way around the color wheel hues are
+/// interpolated, as in CSS `<hue-interpolation-method>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HueArc {
+    /// The shortest way, never more than 180°.
+    #[default]
+    Shorter,
+    /// The longest way, never less than 180°.
+    Longer,
+    /// Always with increasing hue.
+    Increasing,
+    /// Always with decreasing hue.
+    Decreasing,
+}
+
+impl Interpolation {
+    /// Converts a straight sRGB color into this space.
+    pub fn from_srgb(&self, col: Vec4) -> Vec4 {
+        return match self {
+            Interpolation::Srgb => col,
+            Interpolation::LinearSrgb => Vec4::new(srgb_to_linear(col.x), srgb_to_linear(col.y), srgb_to_linear(col.z), col.w),
+            Interpolation::Oklab => srgb_to_oklab(col),
+            Interpolation::Oklch(_) => {
+                let lab = srgb_to_oklab(col);
+                Vec4::new(lab.x, f32::hypot(lab.y, lab.z), lab.z.atan2(lab.y).to_degrees().rem_euclid(360.0), col.w)
+            }
+            Interpolation::Hsl(_) => srgb_to_hsl(col),
+        };
+    }
+
+    /// Converts a color in this space back to sRGB.
+    pub fn to_srgb(&self, col: Vec4) -> Vec4 {
+        return match self {
+            Interpolation::Srgb => col,
+            Interpolation::LinearSrgb => Vec4::new(linear_to_srgb(col.x), linear_to_srgb(col.y), linear_to_srgb(col.z), col.w),
+            Interpolation::Oklab => oklab_to_srgb(col),
+            Interpolation::Oklch(_) => {
+                let (sin, cos) = col.z.to_radians().sin_cos();
+                oklab_to_srgb(Vec4::new(col.x, col.y * cos, col.y * sin, col.w))
+            }
+            Interpolation::Hsl(_) => hsl_to_srgb(col),
+        };
+    }
+
+    // Index of the hue component and the arc it follows,
+    // for the polar spaces.
+    fn hue(&self) -> Option<(usize, HueArc)> {
+        return match self {
+            Interpolation::Oklch(arc) => Some((2, *arc)),
+            Interpolation::Hsl(arc) => Some((0, *arc)),
+            _ => None,
+        };
+    }
+
+    /// # Stops
+    /// Converts two straight sRGB stops into this
+    /// space, ready to be mixed. Hues are adjusted
+    /// to follow the hue arc, and the hue of a grey
+    /// stop is taken from the other one.
+    pub fn stops(&self, from: Vec4, to: Vec4) -> (Vec4, Vec4) {
+        let mut from = self.from_srgb(from);
+        let mut to = self.from_srgb(to);
+        let Some((h, arc)) = self.hue() else { return (from, to); };
+
+        // Chroma in OKLCH and saturation in HSL are both the
+        // second component; when zero the hue means nothing.
+        let grey = |col: Vec4| col[1].abs() < 1e-4;
+        if grey(from) && !grey(to) { from[h] = to[h]; }
+        if grey(to) && !grey(from) { to[h] = from[h]; }
+
+        let delta = to[h] - from[h];
+        match arc {
+            HueArc::Shorter if delta > 180.0 => from[h] += 360.0,
+            HueArc::Shorter if delta < -180.0 => to[h] += 360.0,
+            HueArc::Longer if delta > 0.0 && delta < 180.0 => from[h] += 360.0,
+            HueArc::Longer if delta > -180.0 && delta <= 0.0 => to[h] += 360.0,
+            HueArc::Increasing if delta < 0.0 => to[h] += 360.0,
+            HueArc::Decreasing if delta > 0.0 => from[h] += 360.0,
+            _ => {}
+        }
+        return (from, to);
+    }
+
+    /// # Mix
+    /// Mixes two stops returned by [`Interpolation::stops`],
+    /// in premultiplied form, returning the straight
+    /// sRGB color at `t`.
+    pub fn mix(&self, from: Vec4, to: Vec4, t: f32) -> Vec4 {
+        return self.finish(mix(self.premultiply(from), self.premultiply(to), t));
+    }
+
+    /// # Mix four
+    /// Like [`Interpolation::mix`], for four values of
+    /// `t` at once, one per lane. The stops are only
+    /// premultiplied once.
+    pub fn mix4(&self, from: Vec4, to: Vec4, t: Vec4) -> [Vec4; 4] {
+        let from = self.premultiply(from);
+        let delta = self.premultiply(to) - from;
+        return t.to_array().map(|t| self.finish(from + delta * t));
+    }
+
+    // Premultiplies every component but the hue.
+    fn premultiply(&self, col: Vec4) -> Vec4 {
+        let mut out = col * col.w;
+        if let Some((h, _)) = self.hue() { out[h] = col[h]; }
+        out.w = col.w;
+        return out;
+    }
+
+    // Unpremultiplies a mixed color and converts it
+    // back to sRGB.
+    fn finish(&self, mut col: Vec4) -> Vec4 {
+        let h = self.hue().map(|(h, _)| h);
+        if col.w > 0.0 {
+            let a = col.w;
+            col /= a;
+            if let Some(h) = h { col[h] *= a; }
+            col.w = a;
+        }
+        if let Some(h) = h { col[h] = col[h].rem_euclid(360.0); }
+        return self.to_srgb(col);
+    }
+}
+
+// OKLab, from Björn Ottosson's reference implementation.
+
+fn srgb_to_oklab(col: Vec4) -> Vec4 {
+    let (r, g, b) = (srgb_to_linear(col.x), srgb_to_linear(col.y), srgb_to_linear(col.z));
+    let l = (0.41222147 * r + 0.53633254 * g + 0.05144599 * b).cbrt();
+    let m = (0.21190350 * r + 0.68069955 * g + 0.10739696 * b).cbrt();
+    let s = (0.08830246 * r + 0.28171884 * g + 0.62997870 * b).cbrt();
+    return Vec4::new(
+        0.21045426 * l + 0.79361778 * m - 0.00407205 * s,
+        1.97799850 * l - 2.42859221 * m + 0.45059371 * s,
+        0.02590404 * l + 0.78277177 * m - 0.80867577 * s,
+        col.w,
+    );
+}
+
+fn oklab_to_srgb(col: Vec4) -> Vec4 {
+    let l = (col.x + 0.39633778 * col.y + 0.21580376 * col.z).powi(3);
+    let m = (col.x - 0.10556135 * col.y - 0.06385417 * col.z).powi(3);
+    let s = (col.x - 0.08948418 * col.y - 1.29148555 * col.z).powi(3);
+    return Vec4::new(
+        linear_to_srgb( 4.07674166 * l - 3.30771159 * m + 0.23096993 * s),
+        linear_to_srgb(-1.26843800 * l + 2.60975740 * m - 0.34131940 * s),
+        linear_to_srgb(-0.00419609 * l - 0.70341861 * m + 1.70761470 * s),
+        col.w,
+    );
+}
+
+// HSL, with the hue in degrees and the others in [0, 1].
+
+fn srgb_to_hsl(col: Vec4) -> Vec4 {
+    let max = col.x.max(col.y).max(col.z);
+    let min = col.x.min(col.y).min(col.z);
+    let l = (max + min) / 2.0;
+    let d = max - min;
+    if d <= 0.0 {
+        return Vec4::new(0.0, 0.0, l, col.w);
+    }
+    let s = if l <= 0.5 { d / (max + min) } else { d / (2.0 - max - min) };
+    let h = if max == col.x {
+        (col.y - col.z) / d
+    } else if max == col.y {
+        (col.z - col.x) / d + 2.0
+    } else {
+        (col.x - col.y) / d + 4.0
+    };
+    return Vec4::new((h * 60.0).rem_euclid(360.0), s, l, col.w);
+}
+
+fn hsl_to_srgb(col: Vec4) -> Vec4 {
+    let (h, s, l) = (col.x, col.y, col.z);
+    let f = |n: f32| {
+        let k = (n + h / 30.0).rem_euclid(12.0);
+        let a = s * l.min(1.0 - l);
+        return l - a * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0);
+    };
+    return Vec4::new(f(0.0), f(8.0), f(4.0), col.w);
+}
+
 /// # Solid Fill
 /// A simple solid color fill.
 #[derive(Debug)]
@@ -24,12 +234,40 @@ impl Solid {
             col: col.into()
         }
     }
+
+    /// Creates a fill out of a color given in `space`,
+    /// e.g. a Display P3 one.
+    pub fn in_space(col: [f32; 4], space: ColorSpace) -> Solid {
+        return Solid {
+            col: convert(col.into(), space, ColorSpace::Srgb)
+        }
+    }
 }
 
 impl Shader for Solid {
     fn fill(&self, _x: f32, _y: f32, _w: f32, _h: f32) -> Rgba<f32> {
         return self.col;
     }
+
+    fn fill_span(&self, _x: f32, _y: f32, _step: Vec2, _w: f32, _h: f32, span: &mut [Rgba<f32>]) {
+        span.fill(self.col);
+    }
+}
+
+// Square root of each lane. glam has none, so this uses
+// SSE where it's there, which rounds like `f32::sqrt`.
+fn sqrt4(v: Vec4) -> Vec4 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        use std::arch::x86_64::{_mm_loadu_ps, _mm_sqrt_ps, _mm_storeu_ps};
+        let mut out = [0.0; 4];
+        // SSE is always there on x86_64, and both pointers
+        // point at four floats.
+        unsafe { _mm_storeu_ps(out.as_mut_ptr(), _mm_sqrt_ps(_mm_loadu_ps(v.as_ref().as_ptr()))) };
+        return Vec4::from_array(out);
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    return Vec4::from_array(v.to_array().map(f32::sqrt));
 }
 
 // Simple, two stop radial gradient fill.
@@ -40,31 +278,181 @@ pub struct Radial {
     end: Rgba<f32>,
     center: Vec2,
     scale: f32,
+    interpolation: Interpolation,
+    color_space: ColorSpace,
+    // The stops, converted to the interpolation space.
+    from: Vec4,
+    to: Vec4,
 }
 
 impl Radial {
     pub fn new(start: [f32; 4], end: [f32; 4], center: [f32; 2], scale: f32) -> Radial {
-        return Radial {
+        let mut radial = Radial {
             start: start.into(),
             end: end.into(),
             center: center.into(),
-            scale
-        }
+            scale,
+            interpolation: Interpolation::default(),
+            color_space: ColorSpace::default(),
+            from: Vec4::ZERO,
+            to: Vec4::ZERO,
+        };
+        radial.set_interpolation(Interpolation::default());
+        return radial;
+    }
+
+    pub fn set_interpolation(&mut self, interpolation: Interpolation) {
+        let Rgba { r, g, b, a } = convert(self.start, self.color_space, ColorSpace::Srgb);
+        let from = Vec4::new(r, g, b, a);
+        let Rgba { r, g, b, a } = convert(self.end, self.color_space, ColorSpace::Srgb);
+        (self.from, self.to) = interpolation.stops(from, Vec4::new(r, g, b, a));
+        self.interpolation = interpolation;
+    }
+
+    /// # Set color space
+    /// Sets the space the stops were given in,
+    /// sRGB by default.
+    pub fn set_color_space(&mut self, space: ColorSpace) {
+        self.color_space = space;
+        self.set_interpolation(self.interpolation);
+    }
+
+    fn color(&self, d: f32) -> Rgba<f32> {
+        return Rgba::from(self.interpolation.mix(self.from, self.to, d).to_array());
     }
 }
 
 impl Shader for Radial {
     fn fill(&self, x: f32, y: f32, w: f32, h: f32) -> Rgba<f32> {
-        use std::mem::transmute;
         let (x, y) = (x / w, y / h);
         let d = f32::hypot(x - self.center.x, y - self.center.y) * (1. / self.scale); // from center basically
-        let col = unsafe { // Please find a better way to do this.
-            transmute::<Vec4, Rgba<f32>>(mix(
-                transmute::<Rgba<f32>, Vec4>(self.start),
-                transmute::<Rgba<f32>, Vec4>(self.end),
-                d
-            ))
-        };
-        return col;
+        return self.color(d);
     }
-}
\ No newline at end of file
+
+    // Evaluates the distance for four pixels at a time, one
+    // per lane, then mixes the four colors from stops that
+    // are only premultiplied once.
+    fn fill_span(&self, x: f32, y: f32, step: Vec2, w: f32, h: f32, span: &mut [Rgba<f32>]) {
+        let lanes = Vec4::new(0.0, 1.0, 2.0, 3.0);
+        let inv_scale = 1. / self.scale;
+        let mut chunks = span.chunks_exact_mut(4);
+        let mut i = 0.0;
+        for chunk in &mut chunks {
+            let xs = (Vec4::splat(x) + (lanes + i) * step.x) / w - self.center.x;
+            let ys = (Vec4::splat(y) + (lanes + i) * step.y) / h - self.center.y;
+            let d = sqrt4(xs * xs + ys * ys) * inv_scale;
+            for (pixel, col) in chunk.iter_mut().zip(self.interpolation.mix4(self.from, self.to, d)) {
+                *pixel = Rgba::from(col.to_array());
+            }
+            i += 4.0;
+        }
+        for (j, pixel) in chunks.into_remainder().iter_mut().enumerate() {
+            let p = Vec2::new(x, y) + step * (i + j as f32);
+            *pixel = self.fill(p.x, p.y, w, h);
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use glam::{Vec2, Vec4};
+    use rgb::Rgba;
+
+    use crate::layer::Shader;
+
+    use super::{HueArc, Interpolation, Radial};
+
+    const ARCS: [HueArc; 4] = [HueArc::Shorter, HueArc::Longer, HueArc::Increasing, HueArc::Decreasing];
+
+    #[test]
+    fn hsl_hue_arcs() {
+        // Red is at 0°, blue at 240°. Halfway round the
+        // short way is magenta, the long way green.
+        let (red, blue) = (Vec4::new(1.0, 0.0, 0.0, 1.0), Vec4::new(0.0, 0.0, 1.0, 1.0));
+        let magenta = Vec4::new(1.0, 0.0, 1.0, 1.0);
+        let green = Vec4::new(0.0, 1.0, 0.0, 1.0);
+        for (arc, expected) in ARCS.into_iter().zip([magenta, green, green, magenta]) {
+            let interpolation = Interpolation::Hsl(arc);
+            let (from, to) = interpolation.stops(red, blue);
+            let mixed = interpolation.mix(from, to, 0.5);
+            assert!(mixed.abs_diff_eq(expected, 1e-5), "{arc:?}: {mixed}");
+            assert!(interpolation.mix(from, to, 0.0).abs_diff_eq(red, 1e-5));
+            assert!(interpolation.mix(from, to, 1.0).abs_diff_eq(blue, 1e-5));
+        }
+    }
+
+    #[test]
+    fn oklch_hue_arcs() {
+        let colors = [
+            Vec4::new(1.0, 0.0, 0.0, 1.0),
+            Vec4::new(0.0, 0.0, 1.0, 1.0),
+            Vec4::new(0.2, 0.8, 0.3, 1.0),
+            Vec4::new(0.9, 0.7, 0.1, 1.0),
+        ];
+        for arc in ARCS {
+            let interpolation = Interpolation::Oklch(arc);
+            for a in colors {
+                for b in colors {
+                    let (from, to) = interpolation.stops(a, b);
+                    let delta = to.z - from.z;
+                    let valid = match arc {
+                        HueArc::Shorter => delta.abs() <= 180.0,
+                        HueArc::Longer => delta.abs() >= 180.0 || delta == 0.0,
+                        HueArc::Increasing => delta >= 0.0,
+                        HueArc::Decreasing => delta <= 0.0,
+                    };
+                    assert!(valid, "{arc:?} from {} to {}", from.z, to.z);
+                    assert!(interpolation.mix(from, to, 0.0).abs_diff_eq(a, 1e-4));
+                    assert!(interpolation.mix(from, to, 1.0).abs_diff_eq(b, 1e-4));
+                }
+            }
+        }
+
+        // A grey stop has no hue, so it takes the other one,
+        // and the gradient keeps the hue of blue.
+        let interpolation = Interpolation::Oklch(HueArc::Shorter);
+        let (from, to) = interpolation.stops(Vec4::ONE, colors[1]);
+        assert_eq!(from.z, to.z);
+        let hue = |col: Vec4| interpolation.from_srgb(col).z;
+        let mixed = interpolation.mix(from, to, 0.5);
+        assert!((hue(mixed) - to.z).abs() < 0.5, "{} != {}", hue(mixed), to.z);
+    }
+
+    #[test]
+    fn premultiplied_mixing() {
+        // Fading red out to transparent blue doesn't bring
+        // any blue in. Hues aren't premultiplied, so this
+        // only holds in the rectangular spaces.
+        let (red, clear) = (Vec4::new(1.0, 0.0, 0.0, 1.0), Vec4::new(0.0, 0.0, 1.0, 0.0));
+        for interpolation in [Interpolation::Srgb, Interpolation::LinearSrgb, Interpolation::Oklab] {
+            let (from, to) = interpolation.stops(red, clear);
+            let mixed = interpolation.mix(from, to, 0.5);
+            assert!(mixed.abs_diff_eq(Vec4::new(1.0, 0.0, 0.0, 0.5), 1e-4), "{interpolation:?}: {mixed}");
+        }
+    }
+
+    #[test]
+    fn radial_spans() {
+        let interpolations = [
+            Interpolation::Srgb,
+            Interpolation::LinearSrgb,
+            Interpolation::Oklab,
+            Interpolation::Oklch(HueArc::Longer),
+            Interpolation::Hsl(HueArc::Shorter),
+        ];
+        for interpolation in interpolations {
+            let mut radial = Radial::new([1.0, 0.2, 0.0, 1.0], [0.0, 0.4, 1.0, 0.5], [0.4, 0.6], 0.8);
+            radial.set_interpolation(interpolation);
+            // Eleven pixels leave a remainder after the
+            // groups of four, along a rotated step.
+            let (origin, step) = (Vec2::new(3.5, 20.5), Vec2::new(2.5, 1.25));
+            let mut span = [Rgba::from([0.0; 4]); 11];
+            radial.fill_span(origin.x, origin.y, step, 32.0, 48.0, &mut span);
+            for (i, pixel) in span.iter().enumerate() {
+                let p = origin + step * i as f32;
+                let expected = radial.fill(p.x, p.y, 32.0, 48.0);
+                let error = [pixel.r - expected.r, pixel.g - expected.g, pixel.b - expected.b, pixel.a - expected.a];
+                assert!(error.iter().all(|e| e.abs() <= 1e-6), "{interpolation:?} at {i}: {pixel:?} != {expected:?}");
+            }
+        }
+    }
+}
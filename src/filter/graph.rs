@@ -0,0 +1,799 @@
+//! # Filter graphs
+//! A filter pipeline modeled after SVG's `<filter>`
+//! element. A [`FilterGraph`] is a list of primitives
+//! evaluated in order, each reading one or two images,
+//! either the source or the results of previous
+//! primitives, and producing a new one.
+//!
+//! Primitives follow the definitions of the SVG Filter
+//! Effects specification, working on whatever values
+//! the images hold. Images are premultiplied, and are
+//! only converted to straight alpha where the spec asks
+//! for it.
+
+use std::collections::HashMap;
+
+use glam::{UVec2, Vec2, Vec3, Vec4};
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use rgb::Rgba;
+
+use crate::{compositor::{BlendMode, Operator}, layer::Image, size::pixel_count};
+
+use super::{gaussian_blur, scale_length, separable_xy, translate};
+
+/// # Input
+/// Where a primitive takes an image from.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Input {
+    /// The result of the previous primitive, or the
+    /// source graphic for the first one.
+    #[default]
+    Previous,
+    /// The image the graph is applied to.
+    SourceGraphic,
+    /// The alpha channel of the source graphic, with
+    /// every color set to black.
+    SourceAlpha,
+    /// The result of the primitive with this name.
+    Result(String),
+}
+
+/// # Color matrix
+/// The kinds of `feColorMatrix`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorMatrix {
+    /// A 4x5 matrix, in row major order. The last
+    /// column is added as an offset.
+    Matrix([f32; 20]),
+    /// Saturation, from 0 (greyscale) to 1 (unchanged).
+    Saturate(f32),
+    /// Rotates hues by the given angle, in degrees.
+    HueRotate(f32),
+    /// Moves the luminance of each pixel into its
+    /// alpha, leaving the color black.
+    LuminanceToAlpha,
+}
+
+impl ColorMatrix {
+    pub fn matrix(&self) -> [f32; 20] {
+        return match *self {
+            ColorMatrix::Matrix(matrix) => matrix,
+            ColorMatrix::Saturate(s) => [
+                0.213 + 0.787 * s, 0.715 - 0.715 * s, 0.072 - 0.072 * s, 0.0, 0.0,
+                0.213 - 0.213 * s, 0.715 + 0.285 * s, 0.072 - 0.072 * s, 0.0, 0.0,
+                0.213 - 0.213 * s, 0.715 - 0.715 * s, 0.072 + 0.928 * s, 0.0, 0.0,
+                0.0, 0.0, 0.0, 1.0, 0.0,
+            ],
+            ColorMatrix::HueRotate(degrees) => {
+                let (sin, cos) = degrees.to_radians().sin_cos();
+                [
+                    0.213 + cos * 0.787 - sin * 0.213, 0.715 - cos * 0.715 - sin * 0.715, 0.072 - cos * 0.072 + sin * 0.928, 0.0, 0.0,
+                    0.213 - cos * 0.213 + sin * 0.143, 0.715 + cos * 0.285 + sin * 0.140, 0.072 - cos * 0.072 - sin * 0.283, 0.0, 0.0,
+                    0.213 - cos * 0.213 - sin * 0.787, 0.715 - cos * 0.715 + sin * 0.715, 0.072 + cos * 0.928 + sin * 0.072, 0.0, 0.0,
+                    0.0, 0.0, 0.0, 1.0, 0.0,
+                ]
+            }
+            ColorMatrix::LuminanceToAlpha => [
+                0.0, 0.0, 0.0, 0.0, 0.0,
+                0.0, 0.0, 0.0, 0.0, 0.0,
+                0.0, 0.0, 0.0, 0.0, 0.0,
+                0.2125, 0.7154, 0.0721, 0.0, 0.0,
+            ],
+        };
+    }
+}
+
+/// # Transfer function
+/// The functions of `feComponentTransfer`, remapping
+/// a single channel.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Transfer {
+    #[default]
+    Identity,
+    /// Linear interpolation between evenly spaced values.
+    Table(Vec<f32>),
+    /// Step function between evenly spaced values.
+    Discrete(Vec<f32>),
+    Linear { slope: f32, intercept: f32 },
+    Gamma { amplitude: f32, exponent: f32, offset: f32 },
+}
+
+impl Transfer {
+    pub fn apply(&self, c: f32) -> f32 {
+        return match self {
+            Transfer::Identity => c,
+            Transfer::Table(values) if values.is_empty() => c,
+            Transfer::Table(values) => {
+                let n = (values.len() - 1) as f32;
+                let k = usize::min((c * n).floor() as usize, values.len() - 1);
+                match values.get(k + 1) {
+                    Some(next) => values[k] + (c * n - k as f32) * (next - values[k]),
+                    None => values[k],
+                }
+            }
+            Transfer::Discrete(values) if values.is_empty() => c,
+            Transfer::Discrete(values) => {
+                let n = values.len();
+                values[usize::min((c * n as f32).floor() as usize, n - 1)]
+            }
+            Transfer::Linear { slope, intercept } => slope * c + intercept,
+            Transfer::Gamma { amplitude, exponent, offset } => amplitude * c.powf(*exponent) + offset,
+        };
+    }
+}
+
+/// # Composite operator
+/// The operators of `feComposite`, with `in` as
+/// the source and `in2` as the destination.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompositeOperator {
+    Over,
+    In,
+    Out,
+    Atop,
+    Xor,
+    /// `k1 * in * in2 + k2 * in + k3 * in2 + k4`
+    Arithmetic { k1: f32, k2: f32, k3: f32, k4: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MorphologyOperator {
+    Erode,
+    Dilate,
+}
+
+/// # Edge mode
+/// How `feConvolveMatrix` extends the image past
+/// its borders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EdgeMode {
+    /// Repeats the pixels on the border.
+    #[default]
+    Duplicate,
+    /// Takes pixels from the opposite side.
+    Wrap,
+    /// Uses transparent pixels.
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    R, G, B, A,
+}
+
+impl Channel {
+    fn of(&self, pixel: Rgba<f32>) -> f32 {
+        return match self {
+            Channel::R => pixel.r,
+            Channel::G => pixel.g,
+            Channel::B => pixel.b,
+            Channel::A => pixel.a,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseKind {
+    /// Sum of the absolute value of each octave.
+    Turbulence,
+    /// Sum of each octave, remapped from -1..1 to 0..1.
+    FractalNoise,
+}
+
+/// # Light source
+/// The light sources of the lighting primitives.
+/// Angles are in degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Light {
+    /// Infinitely far away light, like the sun.
+    Distant { azimuth: f32, elevation: f32 },
+    Point { position: Vec3 },
+    Spot {
+        position: Vec3,
+        points_at: Vec3,
+        specular_exponent: f32,
+        /// Maximum angle from the axis of the spot,
+        /// if any.
+        limiting_cone_angle: Option<f32>,
+    },
+}
+
+impl Light {
+    fn scale(&mut self, scale: f32) {
+        match self {
+            Light::Distant { .. } => (),
+            Light::Point { position } => *position *= scale,
+            Light::Spot { position, points_at, .. } => {
+                *position *= scale;
+                *points_at *= scale;
+            }
+        }
+    }
+
+    // Unit vector from the surface point to the light,
+    // and the color of the light reaching it.
+    fn at(&self, surface: Vec3, color: Vec3) -> (Vec3, Vec3) {
+        return match *self {
+            Light::Distant { azimuth, elevation } => {
+                let (az, el) = (azimuth.to_radians(), elevation.to_radians());
+                (Vec3::new(az.cos() * el.cos(), az.sin() * el.cos(), el.sin()), color)
+            }
+            Light::Point { position } => ((position - surface).normalize_or_zero(), color),
+            Light::Spot { position, points_at, specular_exponent, limiting_cone_angle } => {
+                let l = (position - surface).normalize_or_zero();
+                let s = (points_at - position).normalize_or_zero();
+                let cos = -l.dot(s);
+                if cos <= 0.0 || limiting_cone_angle.is_some_and(|angle| cos < angle.to_radians().cos()) {
+                    return (l, Vec3::ZERO);
+                }
+                (l, color * cos.powf(specular_exponent))
+            }
+        };
+    }
+}
+
+/// # Primitive
+/// A single filter operation. The names match the
+/// SVG elements they are modeled after.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Primitive {
+    /// `feColorMatrix`
+    ColorMatrix { input: Input, matrix: ColorMatrix },
+    /// `feComponentTransfer`
+    ComponentTransfer { input: Input, r: Transfer, g: Transfer, b: Transfer, a: Transfer },
+    /// `feComposite`
+    Composite { input: Input, input2: Input, operator: CompositeOperator },
+    /// `feBlend`, blending `input` over `input2`.
+    Blend { input: Input, input2: Input, mode: BlendMode },
+    /// `feOffset`, rounded to whole pixels.
+    Offset { input: Input, offset: Vec2 },
+    /// `feFlood`, with a premultiplied color.
+    Flood { color: Rgba<f32> },
+    /// `feMorphology`
+    Morphology { input: Input, operator: MorphologyOperator, radius: (usize, usize) },
+    /// `feConvolveMatrix`. `kernel` holds `order.0 * order.1`
+    /// values in row major order. When `divisor` is `None`,
+    /// the sum of the kernel is used (or 1 if it is 0), and
+    /// when `target` is `None`, the kernel is centered.
+    ConvolveMatrix {
+        input: Input,
+        order: (usize, usize),
+        kernel: Vec<f32>,
+        divisor: Option<f32>,
+        bias: f32,
+        target: Option<(usize, usize)>,
+        edge_mode: EdgeMode,
+        preserve_alpha: bool,
+    },
+    /// `feDisplacementMap`, moving the pixels of `input`
+    /// by the channels of `input2`.
+    DisplacementMap { input: Input, input2: Input, scale: f32, x_channel: Channel, y_channel: Channel },
+    /// `feTurbulence`. When `stitch` is set, the noise
+    /// tiles seamlessly across the edges of the image.
+    Turbulence { base_frequency: Vec2, octaves: usize, seed: i32, stitch: bool, kind: NoiseKind },
+    /// `feDiffuseLighting`, using the alpha of `input`
+    /// as a height map.
+    DiffuseLighting { input: Input, surface_scale: f32, diffuse_constant: f32, color: Vec3, light: Light },
+    /// `feSpecularLighting`, using the alpha of `input`
+    /// as a height map.
+    SpecularLighting {
+        input: Input,
+        surface_scale: f32,
+        specular_constant: f32,
+        specular_exponent: f32,
+        color: Vec3,
+        light: Light,
+    },
+    /// `feGaussianBlur`
+    GaussianBlur { input: Input, sigma: f32 },
+    /// `feMerge`, stacking every input with source-over,
+    /// the first one at the bottom.
+    Merge { inputs: Vec<Input> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub primitive: Primitive,
+    /// Name other primitives can refer to the result by.
+    pub result: Option<String>,
+}
+
+/// # Filter graph
+/// An ordered list of primitives. The result of the
+/// last one is the output of the graph.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FilterGraph {
+    nodes: Vec<Node>,
+}
+
+impl FilterGraph {
+    pub fn new() -> FilterGraph {
+        return FilterGraph { nodes: vec![] };
+    }
+
+    /// # Push
+    /// Adds a primitive whose result is only used
+    /// by the next one.
+    pub fn push(&mut self, primitive: Primitive) {
+        self.nodes.push(Node { primitive, result: None });
+    }
+
+    /// # Push named
+    /// Adds a primitive whose result can be referenced
+    /// by later ones as `Input::Result(name)`.
+    pub fn push_named(&mut self, primitive: Primitive, name: &str) {
+        self.nodes.push(Node { primitive, result: Some(name.to_string()) });
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        return &self.nodes;
+    }
+
+    /// # Scaled
+    /// Returns a copy of the graph with every length
+    /// (offsets, radii, blurs, displacements, noise
+    /// frequencies and light positions) multiplied by
+    /// `scale`, for images drawn at a device pixel
+    /// ratio of `scale`. Convolution kernels work on
+    /// single pixels, so they are left unchanged.
+    pub fn scaled(&self, scale: f32) -> FilterGraph {
+        let mut graph = self.clone();
+        for node in &mut graph.nodes {
+            match &mut node.primitive {
+                Primitive::Offset { offset, .. } => *offset *= scale,
+                Primitive::Morphology { radius, .. } => *radius = (scale_length(radius.0, scale), scale_length(radius.1, scale)),
+                Primitive::DisplacementMap { scale: displacement, .. } => *displacement *= scale,
+                Primitive::Turbulence { base_frequency, .. } => *base_frequency /= scale,
+                Primitive::DiffuseLighting { light, .. } => light.scale(scale),
+                Primitive::SpecularLighting { light, .. } => light.scale(scale),
+                Primitive::GaussianBlur { sigma, .. } => *sigma *= scale,
+                _ => (),
+            }
+        }
+        return graph;
+    }
+
+    /// # Apply
+    /// Runs every primitive on `source`, returning the
+    /// result of the last one. An empty graph returns
+    /// the source unchanged, and references to unknown
+    /// results read a transparent image, as in SVG.
+    pub fn apply(&self, source: &Image) -> Image {
+        let alpha = source.with_pixels(source.pixels.iter().map(|p| Rgba::from([0.0, 0.0, 0.0, p.a])).collect());
+        let transparent = source.with_pixels(vec![Rgba::from([0.0, 0.0, 0.0, 0.0]); source.pixels.len()]);
+        let mut results: HashMap<&str, Image> = HashMap::new();
+        let mut previous = source.clone();
+
+        for node in &self.nodes {
+            let get = |input: &Input| -> &Image {
+                return match input {
+                    Input::Previous => &previous,
+                    Input::SourceGraphic => source,
+                    Input::SourceAlpha => &alpha,
+                    Input::Result(name) => results.get(name.as_str()).unwrap_or(&transparent),
+                };
+            };
+            let output = evaluate(&node.primitive, get, source.size);
+            if let Some(name) = &node.result {
+                results.insert(name, output.clone());
+            }
+            previous = output;
+        }
+        return previous;
+    }
+}
+
+fn evaluate<'a>(primitive: &Primitive, get: impl Fn(&Input) -> &'a Image, size: UVec2) -> Image {
+    return match primitive {
+        Primitive::ColorMatrix { input, matrix } => color_matrix(get(input), &matrix.matrix()),
+        Primitive::ComponentTransfer { input, r, g, b, a } => map_straight(get(input), |c| {
+            Vec4::new(r.apply(c.x), g.apply(c.y), b.apply(c.z), a.apply(c.w))
+        }),
+        Primitive::Composite { input, input2, operator } => composite(get(input), get(input2), *operator),
+        Primitive::Blend { input, input2, mode } => zip(get(input), get(input2), |src, dst| {
+            Operator::SrcOver.apply(mode.apply(src, dst), dst)
+        }),
+        Primitive::Offset { input, offset } => translate(get(input), *offset, Rgba::from([0.0, 0.0, 0.0, 0.0])),
+        Primitive::Flood { color } => Image::from_pixels(size, vec![*color; pixel_count(size)]),
+        Primitive::Morphology { input, operator, radius } => morphology(get(input), *operator, *radius),
+        Primitive::ConvolveMatrix { input, order, kernel, divisor, bias, target, edge_mode, preserve_alpha } => {
+            let target = target.unwrap_or((order.0 / 2, order.1 / 2));
+            let divisor = divisor.unwrap_or_else(|| {
+                let sum = kernel.iter().sum::<f32>();
+                if sum == 0.0 { 1.0 } else { sum }
+            });
+            convolve_matrix(get(input), *order, kernel, divisor, *bias, target, *edge_mode, *preserve_alpha)
+        }
+        Primitive::DisplacementMap { input, input2, scale, x_channel, y_channel } => {
+            displacement_map(get(input), get(input2), *scale, *x_channel, *y_channel)
+        }
+        Primitive::Turbulence { base_frequency, octaves, seed, stitch, kind } => {
+            Turbulence::new(*seed).render(size, *base_frequency, *octaves, *stitch, *kind)
+        }
+        Primitive::DiffuseLighting { input, surface_scale, diffuse_constant, color, light } => {
+            lighting(get(input), *surface_scale, *color, *light, |n, l, light| {
+                let rgb = (diffuse_constant * n.dot(l)).max(0.0) * light;
+                rgb.extend(1.0)
+            })
+        }
+        Primitive::SpecularLighting { input, surface_scale, specular_constant, specular_exponent, color, light } => {
+            lighting(get(input), *surface_scale, *color, *light, |n, l, light| {
+                let h = (l + Vec3::Z).normalize_or_zero();
+                let rgb = specular_constant * n.dot(h).max(0.0).powf(*specular_exponent) * light;
+                rgb.extend(rgb.max_element())
+            })
+        }
+        Primitive::GaussianBlur { input, sigma } => gaussian_blur(get(input), *sigma),
+        Primitive::Merge { inputs } => {
+            let mut out = Image::from_pixels(size, vec![Rgba::from([0.0, 0.0, 0.0, 0.0]); pixel_count(size)]);
+            for input in inputs {
+                out = zip(get(input), &out, |src, dst| Operator::SrcOver.apply(src, dst));
+            }
+            out
+        }
+    };
+}
+
+fn to_vec4(pixel: Rgba<f32>) -> Vec4 {
+    return Vec4::new(pixel.r, pixel.g, pixel.b, pixel.a);
+}
+
+fn from_vec4(v: Vec4) -> Rgba<f32> {
+    return Rgba::from(v.to_array());
+}
+
+fn unpremultiply(v: Vec4) -> Vec4 {
+    if v.w <= 0.0 { return Vec4::ZERO; }
+    return (v.truncate() / v.w).extend(v.w);
+}
+
+fn premultiply(v: Vec4) -> Vec4 {
+    return (v.truncate() * v.w).extend(v.w);
+}
+
+// Applies `f` to the straight color of each pixel, clamping
+// its result.
+fn map_straight(image: &Image, f: impl Fn(Vec4) -> Vec4 + Sync) -> Image {
+    let pixels = image.pixels.par_iter().map(|pixel| {
+        let out = f(unpremultiply(to_vec4(*pixel))).clamp(Vec4::ZERO, Vec4::ONE);
+        return from_vec4(premultiply(out));
+    }).collect();
+    return image.with_pixels(pixels);
+}
+
+fn zip(a: &Image, b: &Image, f: impl Fn(Rgba<f32>, Rgba<f32>) -> Rgba<f32> + Sync) -> Image {
+    let pixels = a.pixels.par_iter().zip(b.pixels.par_iter()).map(|(a, b)| f(*a, *b)).collect();
+    return a.with_pixels(pixels);
+}
+
+fn color_matrix(image: &Image, m: &[f32; 20]) -> Image {
+    return map_straight(image, |c| {
+        let row = |i: usize| Vec4::new(m[i * 5], m[i * 5 + 1], m[i * 5 + 2], m[i * 5 + 3]).dot(c) + m[i * 5 + 4];
+        return Vec4::new(row(0), row(1), row(2), row(3));
+    });
+}
+
+fn composite(a: &Image, b: &Image, operator: CompositeOperator) -> Image {
+    let operator = match operator {
+        CompositeOperator::Over => Operator::SrcOver,
+        CompositeOperator::In   => Operator::SrcIn,
+        CompositeOperator::Out  => Operator::SrcOut,
+        CompositeOperator::Atop => Operator::SrcAtop,
+        CompositeOperator::Xor  => Operator::Xor,
+        CompositeOperator::Arithmetic { k1, k2, k3, k4 } => return zip(a, b, |a, b| {
+            let (a, b) = (to_vec4(a), to_vec4(b));
+            let out = (k1 * a * b + k2 * a + k3 * b + k4).clamp(Vec4::ZERO, Vec4::ONE);
+            // Keep the result a valid premultiplied color.
+            return from_vec4(out.truncate().min(Vec3::splat(out.w)).extend(out.w));
+        }),
+    };
+    return zip(a, b, |src, dst| operator.apply(src, dst));
+}
+
+fn morphology(image: &Image, operator: MorphologyOperator, radius: (usize, usize)) -> Image {
+    let pick = match operator {
+        MorphologyOperator::Erode => Vec4::min,
+        MorphologyOperator::Dilate => Vec4::max,
+    };
+    let pass = |r: usize| move |line: &[Vec4], out: &mut [Vec4]| {
+        for (i, out) in out.iter_mut().enumerate() {
+            let window = &line[i.saturating_sub(r)..usize::min(i + r + 1, line.len())];
+            *out = window.iter().copied().reduce(pick).unwrap_or(Vec4::ZERO);
+        }
+    };
+    // Minimum and maximum over a rectangle can be taken over
+    // rows first, then over columns.
+    return separable_xy(image, &pass(radius.0), &pass(radius.1));
+}
+
+#[allow(clippy::too_many_arguments)]
+fn convolve_matrix(
+    image: &Image,
+    order: (usize, usize),
+    kernel: &[f32],
+    divisor: f32,
+    bias: f32,
+    target: (usize, usize),
+    edge_mode: EdgeMode,
+    preserve_alpha: bool,
+) -> Image {
+    let (w, h) = (image.size.x as i64, image.size.y as i64);
+    if kernel.len() != order.0 * order.1 || w == 0 || h == 0 {
+        return image.with_pixels(vec![Rgba::from([0.0, 0.0, 0.0, 0.0]); image.pixels.len()]);
+    }
+    let sample = |x: i64, y: i64| -> Vec4 {
+        let (x, y) = match edge_mode {
+            EdgeMode::Duplicate => (x.clamp(0, w - 1), y.clamp(0, h - 1)),
+            EdgeMode::Wrap => (x.rem_euclid(w), y.rem_euclid(h)),
+            EdgeMode::None if x < 0 || y < 0 || x >= w || y >= h => return Vec4::ZERO,
+            EdgeMode::None => (x, y),
+        };
+        let pixel = to_vec4(image.pixels[(y * w + x) as usize]);
+        return if preserve_alpha { unpremultiply(pixel) } else { pixel };
+    };
+    let pixels = (0..w * h).into_par_iter().map(|i| {
+        let (x, y) = (i % w, i / w);
+        let mut sum = Vec4::ZERO;
+        for ky in 0..order.1 {
+            for kx in 0..order.0 {
+                // The kernel is rotated by 180 degrees, as in a true convolution.
+                let weight = kernel[order.0 * order.1 - 1 - (ky * order.0 + kx)];
+                sum += weight * sample(x - target.0 as i64 + kx as i64, y - target.1 as i64 + ky as i64);
+            }
+        }
+        let original = image.pixels[i as usize];
+        if preserve_alpha {
+            let rgb = (sum.truncate() / divisor + bias).clamp(Vec3::ZERO, Vec3::ONE);
+            return from_vec4(premultiply(rgb.extend(original.a)));
+        }
+        // Colors are premultiplied, so the bias added to them
+        // is too, by the alpha of the result.
+        let alpha = (sum.w / divisor + bias).clamp(0.0, 1.0);
+        let rgb = (sum.truncate() / divisor + bias * alpha).clamp(Vec3::ZERO, Vec3::splat(alpha));
+        return from_vec4(rgb.extend(alpha));
+    }).collect();
+    return image.with_pixels(pixels);
+}
+
+fn displacement_map(image: &Image, map: &Image, scale: f32, x_channel: Channel, y_channel: Channel) -> Image {
+    let (w, h) = (image.size.x as i64, image.size.y as i64);
+    let pixels = map.pixels.par_iter().enumerate().map(|(i, displacement)| {
+        let d = from_vec4(unpremultiply(to_vec4(*displacement)));
+        let x = i as i64 % w + (scale * (x_channel.of(d) - 0.5)).round() as i64;
+        let y = i as i64 / w + (scale * (y_channel.of(d) - 0.5)).round() as i64;
+        if x < 0 || y < 0 || x >= w || y >= h {
+            return Rgba::from([0.0, 0.0, 0.0, 0.0]);
+        }
+        return image.pixels[(y * w + x) as usize];
+    }).collect();
+    return image.with_pixels(pixels);
+}
+
+// `shade` returns premultiplied colors: diffuse lighting is
+// opaque, and specular lighting takes the brightest channel
+// as its alpha, with colors already scaled by it.
+fn lighting(
+    image: &Image,
+    surface_scale: f32,
+    color: Vec3,
+    light: Light,
+    shade: impl Fn(Vec3, Vec3, Vec3) -> Vec4 + Sync,
+) -> Image {
+    let (w, h) = (image.size.x as i64, image.size.y as i64);
+    let height = |x: i64, y: i64| image.pixels[(y.clamp(0, h - 1) * w + x.clamp(0, w - 1)) as usize].a;
+    let pixels = (0..w * h).into_par_iter().map(|i| {
+        let (x, y) = (i % w, i / w);
+        // Sobel operator over the height map.
+        let nx = (height(x + 1, y - 1) + 2.0 * height(x + 1, y) + height(x + 1, y + 1))
+            - (height(x - 1, y - 1) + 2.0 * height(x - 1, y) + height(x - 1, y + 1));
+        let ny = (height(x - 1, y + 1) + 2.0 * height(x, y + 1) + height(x + 1, y + 1))
+            - (height(x - 1, y - 1) + 2.0 * height(x, y - 1) + height(x + 1, y - 1));
+        let normal = Vec3::new(-surface_scale * nx / 4.0, -surface_scale * ny / 4.0, 1.0).normalize();
+        let surface = Vec3::new(x as f32, y as f32, surface_scale * height(x, y));
+        let (l, light_color) = light.at(surface, color);
+        let out = shade(normal, l, light_color).clamp(Vec4::ZERO, Vec4::ONE);
+        return from_vec4(out);
+    }).collect();
+    return image.with_pixels(pixels);
+}
+
+// Perlin noise, as in the reference implementation of the
+// SVG specification, so that the same seed gives the same
+// result as in other renderers.
+const B_SIZE: usize = 0x100;
+const B_MASK: i64 = 0xff;
+const PERLIN_N: f64 = 4096.0;
+const RAND_M: i64 = 2147483647;
+const RAND_A: i64 = 16807;
+const RAND_Q: i64 = 127773;
+const RAND_R: i64 = 2836;
+
+struct Turbulence {
+    lattice: [usize; B_SIZE + B_SIZE + 2],
+    gradient: [[[f64; 2]; B_SIZE + B_SIZE + 2]; 4],
+}
+
+#[derive(Clone, Copy)]
+struct Stitch {
+    width: i64,
+    height: i64,
+    wrap_x: i64,
+    wrap_y: i64,
+}
+
+impl Turbulence {
+    fn random(seed: i64) -> i64 {
+        let result = RAND_A * (seed % RAND_Q) - RAND_R * (seed / RAND_Q);
+        return if result <= 0 { result + RAND_M } else { result };
+    }
+
+    fn new(seed: i32) -> Turbulence {
+        let mut seed = seed as i64;
+        if seed <= 0 { seed = -(seed % (RAND_M - 1)) + 1; }
+        if seed > RAND_M - 1 { seed = RAND_M - 1; }
+
+        let mut lattice = [0; B_SIZE + B_SIZE + 2];
+        let mut gradient = [[[0.0; 2]; B_SIZE + B_SIZE + 2]; 4];
+        for channel in gradient.iter_mut() {
+            for (i, g) in channel.iter_mut().take(B_SIZE).enumerate() {
+                lattice[i] = i;
+                for component in g.iter_mut() {
+                    seed = Turbulence::random(seed);
+                    *component = ((seed % (B_SIZE as i64 * 2)) - B_SIZE as i64) as f64 / B_SIZE as f64;
+                }
+                let length = f64::hypot(g[0], g[1]);
+                g[0] /= length;
+                g[1] /= length;
+            }
+        }
+        for i in (1..B_SIZE).rev() {
+            seed = Turbulence::random(seed);
+            let j = (seed % B_SIZE as i64) as usize;
+            lattice.swap(i, j);
+        }
+        for i in 0..B_SIZE + 2 {
+            lattice[B_SIZE + i] = lattice[i];
+            for channel in gradient.iter_mut() {
+                channel[B_SIZE + i] = channel[i];
+            }
+        }
+        return Turbulence { lattice, gradient };
+    }
+
+    fn noise2(&self, channel: usize, v: [f64; 2], stitch: Option<Stitch>) -> f64 {
+        // The lattice coordinates are wrapped for stitching
+        // before they are reduced to the table size.
+        let t = v[0] + PERLIN_N;
+        let mut bx0 = t as i64;
+        let mut bx1 = bx0 + 1;
+        let rx0 = t - (t as i64) as f64;
+        let rx1 = rx0 - 1.0;
+        let t = v[1] + PERLIN_N;
+        let mut by0 = t as i64;
+        let mut by1 = by0 + 1;
+        let ry0 = t - (t as i64) as f64;
+        let ry1 = ry0 - 1.0;
+
+        if let Some(stitch) = stitch {
+            if bx0 >= stitch.wrap_x { bx0 -= stitch.width; }
+            if bx1 >= stitch.wrap_x { bx1 -= stitch.width; }
+            if by0 >= stitch.wrap_y { by0 -= stitch.height; }
+            if by1 >= stitch.wrap_y { by1 -= stitch.height; }
+        }
+        let (bx0, bx1) = ((bx0 & B_MASK) as usize, (bx1 & B_MASK) as usize);
+        let (by0, by1) = ((by0 & B_MASK) as usize, (by1 & B_MASK) as usize);
+
+        let i = self.lattice[bx0];
+        let j = self.lattice[bx1];
+        let b00 = self.lattice[i + by0];
+        let b10 = self.lattice[j + by0];
+        let b01 = self.lattice[i + by1];
+        let b11 = self.lattice[j + by1];
+        let s_curve = |t: f64| t * t * (3.0 - 2.0 * t);
+        let lerp = |t: f64, a: f64, b: f64| a + t * (b - a);
+        let (sx, sy) = (s_curve(rx0), s_curve(ry0));
+        let g = &self.gradient[channel];
+
+        let u = rx0 * g[b00][0] + ry0 * g[b00][1];
+        let v = rx1 * g[b10][0] + ry0 * g[b10][1];
+        let a = lerp(sx, u, v);
+        let u = rx0 * g[b01][0] + ry1 * g[b01][1];
+        let v = rx1 * g[b11][0] + ry1 * g[b11][1];
+        let b = lerp(sx, u, v);
+        return lerp(sy, a, b);
+    }
+
+    // The frequencies, adjusted when stitching so that the
+    // tile borders are continuous, and the stitch info.
+    fn stitching(size: UVec2, frequency: Vec2, stitch: bool) -> ([f64; 2], Option<Stitch>) {
+        let frequency = [frequency.x as f64, frequency.y as f64];
+        if !stitch {
+            return (frequency, None);
+        }
+        let (w, h) = (size.x as f64, size.y as f64);
+        let adjust = |f: f64, length: f64| {
+            if f == 0.0 { return f; }
+            let lo = (length * f).floor() / length;
+            let hi = (length * f).ceil() / length;
+            return if f / lo < hi / f { lo } else { hi };
+        };
+        let fx = adjust(frequency[0], w);
+        let fy = adjust(frequency[1], h);
+        let width = (w * fx + 0.5) as i64;
+        let height = (h * fy + 0.5) as i64;
+        let stitch = Stitch {
+            width,
+            height,
+            wrap_x: PERLIN_N as i64 + width,
+            wrap_y: PERLIN_N as i64 + height,
+        };
+        return ([fx, fy], Some(stitch));
+    }
+
+    fn turbulence(&self, channel: usize, point: [f64; 2], frequency: [f64; 2], octaves: usize, stitching: Option<Stitch>, kind: NoiseKind) -> f64 {
+        let mut stitch = stitching;
+        let mut v = [point[0] * frequency[0], point[1] * frequency[1]];
+        let mut ratio = 1.0;
+        let mut sum = 0.0;
+        for _ in 0..octaves {
+            let noise = self.noise2(channel, v, stitch);
+            sum += match kind {
+                NoiseKind::FractalNoise => noise / ratio,
+                NoiseKind::Turbulence => noise.abs() / ratio,
+            };
+            v = [v[0] * 2.0, v[1] * 2.0];
+            ratio *= 2.0;
+            if let Some(stitch) = stitch.as_mut() {
+                stitch.width *= 2;
+                stitch.wrap_x = 2 * stitch.wrap_x - PERLIN_N as i64;
+                stitch.height *= 2;
+                stitch.wrap_y = 2 * stitch.wrap_y - PERLIN_N as i64;
+            }
+        }
+        return match kind {
+            NoiseKind::FractalNoise => (sum + 1.0) / 2.0,
+            NoiseKind::Turbulence => sum,
+        };
+    }
+
+    fn render(&self, size: UVec2, frequency: Vec2, octaves: usize, stitch: bool, kind: NoiseKind) -> Image {
+        let (frequency, stitching) = Turbulence::stitching(size, frequency, stitch);
+        let pixels = (0..pixel_count(size)).into_par_iter().map(|i| {
+            let point = [(i % size.x as usize) as f64, (i / size.x as usize) as f64];
+            let color = std::array::from_fn(|channel| {
+                return self.turbulence(channel, point, frequency, octaves, stitching, kind) as f32;
+            });
+            return from_vec4(premultiply(Vec4::from_array(color).clamp(Vec4::ZERO, Vec4::ONE)));
+        }).collect();
+        return Image::from_pixels(size, pixels);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{UVec2, Vec2};
+
+    use super::{NoiseKind, Turbulence};
+
+    #[test]
+    fn turbulence_stitch() {
+        let size = UVec2::new(37, 23);
+        let (frequency, stitching) = Turbulence::stitching(size, Vec2::new(0.07, 0.11), true);
+        let turbulence = Turbulence::new(7);
+        for kind in [NoiseKind::FractalNoise, NoiseKind::Turbulence] {
+            let noise = |x: u32, y: u32| turbulence.turbulence(0, [x as f64, y as f64], frequency, 3, stitching, kind);
+            // The column after the right edge wraps to the left
+            // one, and the row below the bottom to the top one.
+            for y in 0..size.y {
+                assert!((noise(0, y) - noise(size.x, y)).abs() < 1e-6);
+            }
+            for x in 0..size.x {
+                assert!((noise(x, 0) - noise(x, size.y)).abs() < 1e-6);
+            }
+        }
+        // Without stitching, the edges differ.
+        let (frequency, _) = Turbulence::stitching(size, Vec2::new(0.07, 0.11), false);
+        let noise = |x: u32| turbulence.turbulence(0, [x as f64, 5.0], frequency, 3, None, NoiseKind::FractalNoise);
+        assert!((noise(0) - noise(size.x)).abs() > 1e-3);
+    }
+}
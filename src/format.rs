@@ -0,0 +1,473 @@
+//! # Pixel formats
+//! Conversion of [`Image`]s to the packed integer
+//! formats used by window surfaces and displays.
+//!
+//! Channels are rounded to the nearest level, or
+//! dithered to break up the banding that few levels
+//! leave in smooth gradients.
+//! 
+//! Layers and images can also be drawn straight into
+//! a buffer owned by the caller, see [`Target`].
+
+use std::{borrow::Cow, sync::OnceLock};
+
+use rayon::{iter::{IndexedParallelIterator, ParallelIterator}, slice::{ParallelSlice, ParallelSliceMut}};
+use rgb::Rgba;
+
+use crate::{color::{decode_premultiplied, encode_premultiplied, premultiply, unpremultiply, ColorSpace}, compositor::Operator, layer::Image};
+
+/// # Pixel format
+/// A packed pixel format, with 8 bits or fewer
+/// per channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PixelFormat {
+    /// Red, green, blue and alpha, a byte each.
+    #[default]
+    Rgba8,
+    /// Blue, green, red and alpha, a byte each. Read
+    /// as a little endian `u32`, this is `0xAARRGGBB`,
+    /// the format of most window surfaces.
+    Bgra8,
+    /// 5 bits of red, 6 of green and 5 of blue, in a
+    /// native endian `u16`. Alpha is dropped.
+    Rgb565,
+    /// The luma of the color, with the Rec. 709
+    /// weights. Alpha is dropped.
+    Grey8,
+}
+
+impl PixelFormat {
+    /// The size of a pixel, in bytes.
+    pub fn pixel_size(&self) -> usize {
+        return match self {
+            PixelFormat::Rgba8 | PixelFormat::Bgra8 => 4,
+            PixelFormat::Rgb565 => 2,
+            PixelFormat::Grey8 => 1,
+        };
+    }
+
+    /// # Encode
+    /// Packs a pixel into `out`, quantizing each channel
+    /// as `floor(c * max + threshold)`. A threshold of
+    /// 0.5 rounds to the nearest level.
+    pub fn encode(&self, pixel: Rgba<f32>, threshold: f32, out: &mut [u8]) {
+        let q = |c: f32, max: f32| (c.clamp(0.0, 1.0) * max + threshold).floor().min(max) as u16;
+        match self {
+            PixelFormat::Rgba8 => {
+                out[..4].copy_from_slice(&[pixel.r, pixel.g, pixel.b, pixel.a].map(|c| q(c, 255.0) as u8));
+            }
+            PixelFormat::Bgra8 => {
+                out[..4].copy_from_slice(&[pixel.b, pixel.g, pixel.r, pixel.a].map(|c| q(c, 255.0) as u8));
+            }
+            PixelFormat::Rgb565 => {
+                let packed = q(pixel.r, 31.0) << 11 | q(pixel.g, 63.0) << 5 | q(pixel.b, 31.0);
+                out[..2].copy_from_slice(&packed.to_ne_bytes());
+            }
+            PixelFormat::Grey8 => {
+                out[0] = q(0.2126 * pixel.r + 0.7152 * pixel.g + 0.0722 * pixel.b, 255.0) as u8;
+            }
+        }
+    }
+
+    /// # Encode premultiplied
+    /// Packs a premultiplied pixel like [`encode`],
+    /// then clamps each color channel to the quantized
+    /// alpha, which dithering may otherwise round below
+    /// them.
+    /// 
+    /// [`encode`]: PixelFormat::encode
+    pub fn encode_premultiplied(&self, pixel: Rgba<f32>, threshold: f32, out: &mut [u8]) {
+        self.encode(pixel, threshold, out);
+        if let PixelFormat::Rgba8 | PixelFormat::Bgra8 = self {
+            let a = out[3];
+            out[..3].iter_mut().for_each(|c| *c = (*c).min(a));
+        }
+    }
+
+    /// # Decode
+    /// Unpacks a pixel. Formats without alpha are
+    /// read as opaque.
+    pub fn decode(&self, bytes: &[u8]) -> Rgba<f32> {
+        let n = |c: u8| c as f32 / 255.0;
+        return match self {
+            PixelFormat::Rgba8 => Rgba::from([n(bytes[0]), n(bytes[1]), n(bytes[2]), n(bytes[3])]),
+            PixelFormat::Bgra8 => Rgba::from([n(bytes[2]), n(bytes[1]), n(bytes[0]), n(bytes[3])]),
+            PixelFormat::Rgb565 => {
+                let packed = u16::from_ne_bytes([bytes[0], bytes[1]]);
+                let r = (packed >> 11) as f32 / 31.0;
+                let g = ((packed >> 5) & 0x3f) as f32 / 63.0;
+                let b = (packed & 0x1f) as f32 / 31.0;
+                Rgba::from([r, g, b, 1.0])
+            }
+            PixelFormat::Grey8 => Rgba::from([n(bytes[0]), n(bytes[0]), n(bytes[0]), 1.0]),
+        };
+    }
+}
+
+/// # Dither
+/// How channels are quantized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dither {
+    /// Round to the nearest level.
+    #[default]
+    None,
+    /// An 8x8 Bayer matrix. Cheap, but leaves a
+    /// visible cross-hatch pattern.
+    Ordered,
+    /// A 64x64 blue noise texture, which spreads the
+    /// error without any visible structure.
+    BlueNoise,
+}
+
+impl Dither {
+    /// The threshold used at pixel `(x, y)`, in `[0, 1)`.
+    pub fn threshold(&self, x: usize, y: usize) -> f32 {
+        return match self {
+            Dither::None => 0.5,
+            Dither::Ordered => (BAYER[y % 8][x % 8] as f32 + 0.5) / 64.0,
+            Dither::BlueNoise => {
+                let rank = blue_noise()[(y % NOISE_SIZE) * NOISE_SIZE + x % NOISE_SIZE];
+                (rank as f32 + 0.5) / (NOISE_SIZE * NOISE_SIZE) as f32
+            }
+        };
+    }
+}
+
+/// # Quantization
+/// How an image is converted to a pixel format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quantization {
+    pub format: PixelFormat,
+    pub dither: Dither,
+    /// Whether to write straight colors instead of
+    /// premultiplied ones.
+    pub unpremultiply: bool,
+}
+
+impl Quantization {
+    /// Converts to `format`, rounding premultiplied
+    /// colors.
+    pub fn new(format: PixelFormat) -> Quantization {
+        return Quantization { format, dither: Dither::None, unpremultiply: false };
+    }
+
+    pub fn set_dither(&mut self, dither: Dither) {
+        self.dither = dither;
+    }
+
+    pub fn set_unpremultiply(&mut self, unpremultiply: bool) {
+        self.unpremultiply = unpremultiply;
+    }
+}
+
+impl Image {
+    /// # Quantize
+    /// Converts the image to a packed pixel format,
+    /// with rows one after the other. Pixel formats
+    /// hold sRGB colors, so images in other color
+    /// spaces are converted first.
+    pub fn quantize(&self, quantization: Quantization) -> Vec<u8> {
+        let image = match self.color_space {
+            ColorSpace::Srgb => Cow::Borrowed(self),
+            _ => {
+                let mut image = self.clone();
+                image.convert(ColorSpace::Srgb);
+                Cow::Owned(image)
+            }
+        };
+        let width = self.size.x as usize;
+        let size = quantization.format.pixel_size();
+        let mut out = vec![0; image.pixels.len() * size];
+        out.par_chunks_mut((width * size).max(1))
+            .zip(image.pixels.par_chunks(width.max(1)))
+            .enumerate()
+            .for_each(|(y, (row, pixels))| {
+                for (x, (out, pixel)) in row.chunks_exact_mut(size).zip(pixels).enumerate() {
+                    let threshold = quantization.dither.threshold(x, y);
+                    if quantization.unpremultiply {
+                        quantization.format.encode(unpremultiply(*pixel), threshold, out);
+                    } else {
+                        quantization.format.encode_premultiplied(*pixel, threshold, out);
+                    }
+                }
+            });
+        return out;
+    }
+}
+
+/// # Target
+/// A pixel buffer owned by the caller, e.g. the
+/// framebuffer of a window, that layers and images
+/// are composited onto.
+/// 
+/// The buffer is assumed to hold sRGB colors; what
+/// is drawn is converted to its format and composited
+/// over the existing content.
+#[derive(Debug)]
+pub struct Target<'buf> {
+    data: &'buf mut [u8],
+    pub width: usize,
+    pub height: usize,
+    pub format: PixelFormat,
+    /// The distance between the start of two rows,
+    /// in bytes.
+    pub stride: usize,
+    /// Where the first pixel starts, in bytes.
+    pub offset: usize,
+    /// Whether the buffer holds premultiplied colors.
+    pub premultiplied: bool,
+    pub dither: Dither,
+}
+
+impl<'buf> Target<'buf> {
+    /// Wraps a buffer of premultiplied pixels with
+    /// rows packed one after the other.
+    pub fn new(data: &'buf mut [u8], width: usize, height: usize, format: PixelFormat) -> Target<'buf> {
+        return Target {
+            data,
+            width,
+            height,
+            format,
+            stride: width * format.pixel_size(),
+            offset: 0,
+            premultiplied: true,
+            dither: Dither::None,
+        };
+    }
+
+    /// Wraps a buffer of `u32` pixels, as handed out
+    /// by most windowing libraries. On little endian
+    /// machines each pixel reads as `0xAARRGGBB`.
+    pub fn from_u32(data: &'buf mut [u32], width: usize, height: usize) -> Target<'buf> {
+        let len = std::mem::size_of_val(data);
+        // SAFETY: u8 has no alignment requirements and every
+        // byte of a u32 is a valid u8, so the same memory can
+        // be viewed as bytes for as long as it is borrowed.
+        let bytes = unsafe { std::slice::from_raw_parts_mut(data.as_mut_ptr() as *mut u8, len) };
+        return Target::new(bytes, width, height, PixelFormat::Bgra8);
+    }
+
+    pub fn set_stride(&mut self, stride: usize) {
+        self.stride = stride;
+    }
+
+    pub fn set_offset(&mut self, offset: usize) {
+        self.offset = offset;
+    }
+
+    pub fn set_premultiplied(&mut self, premultiplied: bool) {
+        self.premultiplied = premultiplied;
+    }
+
+    pub fn set_dither(&mut self, dither: Dither) {
+        self.dither = dither;
+    }
+
+    /// The underlying buffer.
+    pub fn data(&mut self) -> &mut [u8] {
+        return self.data;
+    }
+
+    /// # Composite
+    /// Combines `image` with the buffer using `op`,
+    /// placing it at the top left corner. Whatever
+    /// falls outside of the buffer is cut off.
+    pub fn composite(&mut self, image: &Image, op: Operator) {
+        let linear = image.color_space == ColorSpace::LinearSrgb;
+        let image = match image.color_space {
+            ColorSpace::Srgb | ColorSpace::LinearSrgb => Cow::Borrowed(image),
+            _ => {
+                let mut image = image.clone();
+                image.convert(ColorSpace::Srgb);
+                Cow::Owned(image)
+            }
+        };
+        let width = image.size.x as usize;
+        self.draw(width, image.size.y as usize, linear, op, |y, row| {
+            row.copy_from_slice(&image.pixels[y * width..y * width + row.len()]);
+        });
+    }
+
+    /// # Draw
+    /// Composites premultiplied pixels produced one
+    /// row at a time by `row`, which receives the index
+    /// of the row and a transparent buffer to fill.
+    /// Rows are drawn in parallel.
+    /// 
+    /// If `linear` is set, the pixels are in linear
+    /// light and are composited as such.
+    /// 
+    /// # Panics
+    /// If the buffer is too small for the given size,
+    /// stride and offset.
+    pub fn draw(
+        &mut self,
+        width: usize,
+        height: usize,
+        linear: bool,
+        op: Operator,
+        row: impl Fn(usize, &mut [Rgba<f32>]) + Sync,
+    ) {
+        let size = self.format.pixel_size();
+        assert!(self.stride >= self.width * size, "The stride is shorter than a row!");
+        let end = match self.height {
+            0 => self.offset,
+            h => self.offset + self.stride * (h - 1) + self.width * size,
+        };
+        assert!(end <= self.data.len(), "The buffer is too small!");
+
+        let (width, height) = (width.min(self.width), height.min(self.height));
+        if width == 0 || height == 0 { return; }
+        let (format, premultiplied, dither) = (self.format, self.premultiplied, self.dither);
+        let transparent = Rgba::from([0.0, 0.0, 0.0, 0.0]);
+        self.data[self.offset..end].par_chunks_mut(self.stride.max(1))
+            .take(height)
+            .enumerate()
+            .for_each(|(y, bytes)| {
+                let mut pixels = vec![transparent; width];
+                row(y, &mut pixels);
+                for (x, (src, bytes)) in pixels.iter().zip(bytes.chunks_exact_mut(size)).enumerate() {
+                    // Nothing to draw, and no need to quantize
+                    // the pixel again.
+                    if src.a <= 0.0 && op == Operator::SrcOver { continue; }
+                    let mut dst = format.decode(bytes);
+                    if !premultiplied { dst = premultiply(dst); }
+                    if linear { dst = decode_premultiplied(dst); }
+                    let mut out = op.apply(*src, dst);
+                    if linear { out = encode_premultiplied(out); }
+                    if premultiplied {
+                        format.encode_premultiplied(out, dither.threshold(x, y), bytes);
+                    } else {
+                        format.encode(unpremultiply(out), dither.threshold(x, y), bytes);
+                    }
+                }
+            });
+    }
+}
+
+const BAYER: [[u8; 8]; 8] = [
+    [ 0, 32,  8, 40,  2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44,  4, 36, 14, 46,  6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [ 3, 35, 11, 43,  1, 33,  9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47,  7, 39, 13, 45,  5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+const NOISE_SIZE: usize = 64;
+
+// The ranks of a blue noise texture, generated once
+// with Ulichney's void and cluster method.
+fn blue_noise() -> &'static [u16] {
+    static NOISE: OnceLock<Vec<u16>> = OnceLock::new();
+    return NOISE.get_or_init(void_and_cluster);
+}
+
+fn void_and_cluster() -> Vec<u16> {
+    const N: usize = NOISE_SIZE * NOISE_SIZE;
+    let sigma = 1.5f32;
+
+    // Gaussian falloff by toroidal offset, so the texture tiles.
+    let mut falloff = vec![0.0f32; N];
+    for dy in 0..NOISE_SIZE {
+        for dx in 0..NOISE_SIZE {
+            let x = dx.min(NOISE_SIZE - dx) as f32;
+            let y = dy.min(NOISE_SIZE - dy) as f32;
+            falloff[dy * NOISE_SIZE + dx] = (-(x * x + y * y) / (2.0 * sigma * sigma)).exp();
+        }
+    }
+    let splat = |energy: &mut [f32], p: usize, sign: f32| {
+        let (px, py) = (p % NOISE_SIZE, p / NOISE_SIZE);
+        for (q, e) in energy.iter_mut().enumerate() {
+            let dx = (q % NOISE_SIZE + NOISE_SIZE - px) % NOISE_SIZE;
+            let dy = (q / NOISE_SIZE + NOISE_SIZE - py) % NOISE_SIZE;
+            *e += sign * falloff[dy * NOISE_SIZE + dx];
+        }
+    };
+    // The tightest cluster is the set pixel with the most
+    // energy, the largest void the unset one with the least.
+    let tightest = |pattern: &[bool], energy: &[f32]| {
+        return (0..N).filter(|&p| pattern[p]).max_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap();
+    };
+    let largest = |pattern: &[bool], energy: &[f32]| {
+        return (0..N).filter(|&p| !pattern[p]).min_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap();
+    };
+
+    // A fixed seed keeps the texture the same across runs.
+    let mut seed = 0x2545f491u32;
+    let mut pattern = vec![false; N];
+    let mut energy = vec![0.0f32; N];
+    let mut ones = 0;
+    while ones < N / 10 {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        let p = seed as usize % N;
+        if !pattern[p] {
+            pattern[p] = true;
+            splat(&mut energy, p, 1.0);
+            ones += 1;
+        }
+    }
+
+    // Move points from clusters to voids until stable. It
+    // takes a few hundred swaps; the bound is only a guard.
+    for _ in 0..N {
+        let cluster = tightest(&pattern, &energy);
+        pattern[cluster] = false;
+        splat(&mut energy, cluster, -1.0);
+        let void = largest(&pattern, &energy);
+        pattern[void] = true;
+        splat(&mut energy, void, 1.0);
+        if void == cluster { break; }
+    }
+
+    let mut ranks = vec![0u16; N];
+    // Rank the initial points by removing clusters...
+    let (mut removing, mut removed) = (pattern.clone(), energy.clone());
+    for rank in (0..ones).rev() {
+        let cluster = tightest(&removing, &removed);
+        removing[cluster] = false;
+        splat(&mut removed, cluster, -1.0);
+        ranks[cluster] = rank as u16;
+    }
+    // ...and the rest by filling voids.
+    for rank in ones..N {
+        let void = largest(&pattern, &energy);
+        pattern[void] = true;
+        splat(&mut energy, void, 1.0);
+        ranks[void] = rank as u16;
+    }
+    return ranks;
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::UVec2;
+    use rgb::Rgba;
+
+    use crate::{color::ColorSpace, compositor::Operator, layer::Image};
+
+    use super::{Dither, PixelFormat, Quantization, Target};
+
+    #[test]
+    fn premultiplied_colors_below_alpha() {
+        // Display P3 red is brighter than sRGB red, so it
+        // comes out of the conversion above its alpha.
+        let mut image = Image::from_pixels(UVec2::new(2, 1), vec![Rgba::from([0.5, 0.0, 0.0, 0.5]); 2]);
+        image.color_space = ColorSpace::DisplayP3;
+        for format in [PixelFormat::Rgba8, PixelFormat::Bgra8] {
+            let mut quantization = Quantization::new(format);
+            quantization.set_dither(Dither::Ordered);
+            let bytes = image.quantize(quantization);
+            assert!(bytes.chunks(4).all(|p| p[..3].iter().all(|&c| c <= p[3])), "{bytes:?}");
+
+            let mut data = [0; 8];
+            let mut target = Target::new(&mut data, 2, 1, format);
+            target.composite(&image, Operator::SrcOver);
+            assert!(data.chunks(4).all(|p| p[..3].iter().all(|&c| c <= p[3]) && p[3] > 0), "{data:?}");
+        }
+    }
+}
@@ -0,0 +1,440 @@
+//! # Image operations
+//! Pixel access and editing for [`Image`]: reading
+//! and writing single pixels, sub-region views,
+//! blitting, cropping, flipping, rotating and
+//! resampling.
+//!
+//! Pixels are premultiplied, like everywhere else,
+//! and coordinates are in pixels with the origin
+//! at the top left corner.
+
+use std::borrow::Cow;
+
+use rayon::{iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator}, slice::{ParallelSlice, ParallelSliceMut}};
+use glam::UVec2;
+use rgb::Rgba;
+
+use crate::{color::ColorSpace, compositor::Operator, layer::Image};
+
+impl Image {
+    pub fn width(&self) -> usize {
+        return self.size.x as usize;
+    }
+
+    pub fn height(&self) -> usize {
+        return self.size.y as usize;
+    }
+
+    /// Returns the pixel at `(x, y)`, if inside the image.
+    pub fn get(&self, x: usize, y: usize) -> Option<Rgba<f32>> {
+        if x >= self.width() || y >= self.height() { return None; }
+        return self.pixels.get(y * self.width() + x).copied();
+    }
+
+    /// Returns a mutable reference to the pixel at
+    /// `(x, y)`, if inside the image.
+    pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut Rgba<f32>> {
+        if x >= self.width() || y >= self.height() { return None; }
+        let width = self.width();
+        return self.pixels.get_mut(y * width + x);
+    }
+
+    /// # Set
+    /// Replaces the pixel at `(x, y)`.
+    ///
+    /// # Panics
+    /// If `(x, y)` is outside of the image.
+    pub fn set(&mut self, x: usize, y: usize, pixel: Rgba<f32>) {
+        *self.get_mut(x, y).expect("Pixel out of bounds!") = pixel;
+    }
+
+    /// Iterates over the rows of the image, top to bottom.
+    pub fn rows(&self) -> std::slice::ChunksExact<'_, Rgba<f32>> {
+        return self.pixels.chunks_exact(self.width().max(1));
+    }
+
+    /// Iterates mutably over the rows of the image,
+    /// top to bottom.
+    pub fn rows_mut(&mut self) -> std::slice::ChunksExactMut<'_, Rgba<f32>> {
+        let width = self.width().max(1);
+        return self.pixels.chunks_exact_mut(width);
+    }
+
+    /// # View
+    /// Borrows a rectangular region of the image,
+    /// clipped to its bounds.
+    pub fn view(&self, x: usize, y: usize, width: usize, height: usize) -> View<'_> {
+        let (x, y, width, height) = clip(self.width(), self.height(), x as i64, y as i64, width, height);
+        return View { pixels: &self.pixels, stride: self.width(), x, y, width, height };
+    }
+
+    /// # View mut
+    /// Mutably borrows a rectangular region of the
+    /// image, clipped to its bounds.
+    pub fn view_mut(&mut self, x: usize, y: usize, width: usize, height: usize) -> ViewMut<'_> {
+        let stride = self.width();
+        let (x, y, width, height) = clip(stride, self.height(), x as i64, y as i64, width, height);
+        return ViewMut { pixels: &mut self.pixels, color_space: self.color_space, stride, x, y, width, height };
+    }
+
+    /// # Blit
+    /// Combines `src` with this image using `op`,
+    /// with its top left corner at `(x, y)`. Parts
+    /// falling outside of this image are cut off.
+    /// `src` is first converted to the color space
+    /// of this image.
+    pub fn blit(&mut self, src: &Image, x: i64, y: i64, op: Operator) {
+        let src = converted(src, self.color_space);
+        let width = self.width();
+        let (dx, dy, w, h) = clip(width, self.height(), x, y, src.width(), src.height());
+        // Where the clipped region starts in `src`.
+        let (sx, sy) = ((dx as i64 - x) as usize, (dy as i64 - y) as usize);
+        self.pixels.par_chunks_mut(width.max(1))
+            .skip(dy)
+            .take(h)
+            .enumerate()
+            .for_each(|(row, dst)| {
+                let src = &src.pixels[(sy + row) * src.width() + sx..][..w];
+                for (dst, src) in dst[dx..dx + w].iter_mut().zip(src) {
+                    *dst = op.apply(*src, *dst);
+                }
+            });
+    }
+
+    /// # Fill rect
+    /// Replaces the pixels of a rectangle, clipped
+    /// to the image, with `pixel`.
+    pub fn fill_rect(&mut self, x: i64, y: i64, width: usize, height: usize, pixel: Rgba<f32>) {
+        let stride = self.width();
+        let (x, y, width, height) = clip(stride, self.height(), x, y, width, height);
+        ViewMut { pixels: &mut self.pixels, color_space: self.color_space, stride, x, y, width, height }.fill(pixel);
+    }
+
+    /// # Crop
+    /// Copies a rectangular region, clipped to the
+    /// bounds of the image, into a new image.
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> Image {
+        return self.view(x, y, width, height).to_image(self);
+    }
+
+    /// Mirrors the image left to right.
+    pub fn flip_horizontal(&mut self) {
+        self.rows_mut().for_each(|row| row.reverse());
+    }
+
+    /// Mirrors the image top to bottom.
+    pub fn flip_vertical(&mut self) {
+        let (width, height) = (self.width(), self.height());
+        for y in 0..height / 2 {
+            let (top, bottom) = self.pixels.split_at_mut((height - 1 - y) * width);
+            top[y * width..(y + 1) * width].swap_with_slice(&mut bottom[..width]);
+        }
+    }
+
+    /// Rotates the image by 180°.
+    pub fn rotate_180(&mut self) {
+        self.pixels.reverse();
+    }
+
+    /// Returns the image rotated by 90°, clockwise.
+    pub fn rotate_90(&self) -> Image {
+        let (width, height) = (self.width(), self.height());
+        return self.rotated(|x, y| self.pixels[(height - 1 - x) * width + y]);
+    }
+
+    /// Returns the image rotated by 270° clockwise,
+    /// that is 90° counterclockwise.
+    pub fn rotate_270(&self) -> Image {
+        let width = self.width();
+        return self.rotated(|x, y| self.pixels[x * width + (width - 1 - y)]);
+    }
+
+    // Builds a width-for-height image, reading each of its
+    // pixels from `(x, y)` through `f`.
+    fn rotated(&self, f: impl Fn(usize, usize) -> Rgba<f32> + Sync) -> Image {
+        let (width, height) = (self.height(), self.width());
+        let mut pixels = vec![Rgba::from([0.0, 0.0, 0.0, 0.0]); width * height];
+        pixels.par_chunks_mut(width.max(1)).enumerate().for_each(|(y, row)| {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = f(x, y);
+            }
+        });
+        let mut image = self.with_pixels(pixels);
+        image.size = UVec2::new(width as u32, height as u32);
+        return image;
+    }
+
+    /// # Resize
+    /// Resamples the image to a new size with `filter`,
+    /// one direction at a time.
+    ///
+    /// Pixels are filtered as they are, so resizing
+    /// images tagged as linear light gives the most
+    /// accurate result. Lanczos and Mitchell can ring
+    /// slightly around hard edges; alpha is clamped
+    /// to `[0, 1]`.
+    pub fn resize(&self, width: usize, height: usize, filter: ResizeFilter) -> Image {
+        let (src_width, src_height) = (self.width(), self.height());
+        let transparent = Rgba::from([0.0, 0.0, 0.0, 0.0]);
+        if width == 0 || height == 0 || src_width == 0 || src_height == 0 {
+            let mut image = self.with_pixels(vec![transparent; width * height]);
+            image.size = UVec2::new(width as u32, height as u32);
+            return image;
+        }
+
+        // Horizontal pass, into a width by src_height buffer.
+        let columns = contributions(src_width, width, filter);
+        let mut wide = vec![transparent; width * src_height];
+        wide.par_chunks_mut(width).zip(self.pixels.par_chunks(src_width)).for_each(|(out, row)| {
+            for (pixel, (start, weights)) in out.iter_mut().zip(&columns) {
+                *pixel = weigh(weights.iter().enumerate().map(|(i, w)| (row[start + i], *w)));
+            }
+        });
+
+        // Vertical pass.
+        let rows = contributions(src_height, height, filter);
+        let mut pixels = vec![transparent; width * height];
+        pixels.par_chunks_mut(width).zip(rows.par_iter()).for_each(|(out, (start, weights))| {
+            for (x, pixel) in out.iter_mut().enumerate() {
+                *pixel = weigh(weights.iter().enumerate().map(|(i, w)| (wide[(start + i) * width + x], *w)));
+            }
+        });
+
+        let mut image = self.with_pixels(pixels);
+        image.size = UVec2::new(width as u32, height as u32);
+        return image;
+    }
+}
+
+/// # View
+/// A rectangular region of an [`Image`].
+#[derive(Debug, Clone, Copy)]
+pub struct View<'a> {
+    pixels: &'a [Rgba<f32>],
+    stride: usize,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+impl<'a> View<'a> {
+    pub fn width(&self) -> usize {
+        return self.width;
+    }
+
+    pub fn height(&self) -> usize {
+        return self.height;
+    }
+
+    /// Returns the pixel at `(x, y)`, relative to
+    /// the view, if inside it.
+    pub fn get(&self, x: usize, y: usize) -> Option<Rgba<f32>> {
+        if x >= self.width || y >= self.height { return None; }
+        return Some(self.pixels[(self.y + y) * self.stride + self.x + x]);
+    }
+
+    /// Returns row `y` of the view.
+    pub fn row(&self, y: usize) -> &'a [Rgba<f32>] {
+        let start = (self.y + y) * self.stride + self.x;
+        return &self.pixels[start..start + self.width];
+    }
+
+    /// Iterates over the rows of the view, top to bottom.
+    pub fn rows(&self) -> impl Iterator<Item = &'a [Rgba<f32>]> + '_ {
+        return (0..self.height).map(|y| self.row(y));
+    }
+
+    // Copies the view into a new image, tagged like `image`.
+    fn to_image(self, image: &Image) -> Image {
+        let pixels = self.rows().flatten().copied().collect();
+        let mut image = image.with_pixels(pixels);
+        image.size = UVec2::new(self.width as u32, self.height as u32);
+        return image;
+    }
+}
+
+/// # View mut
+/// A mutable rectangular region of an [`Image`].
+#[derive(Debug)]
+pub struct ViewMut<'a> {
+    pixels: &'a mut [Rgba<f32>],
+    color_space: ColorSpace,
+    stride: usize,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+impl<'a> ViewMut<'a> {
+    pub fn width(&self) -> usize {
+        return self.width;
+    }
+
+    pub fn height(&self) -> usize {
+        return self.height;
+    }
+
+    /// Returns the pixel at `(x, y)`, relative to
+    /// the view, if inside it.
+    pub fn get(&self, x: usize, y: usize) -> Option<Rgba<f32>> {
+        if x >= self.width || y >= self.height { return None; }
+        return Some(self.pixels[(self.y + y) * self.stride + self.x + x]);
+    }
+
+    /// Returns a mutable reference to the pixel at
+    /// `(x, y)`, relative to the view, if inside it.
+    pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut Rgba<f32>> {
+        if x >= self.width || y >= self.height { return None; }
+        return Some(&mut self.pixels[(self.y + y) * self.stride + self.x + x]);
+    }
+
+    /// Returns row `y` of the view.
+    pub fn row_mut(&mut self, y: usize) -> &mut [Rgba<f32>] {
+        let start = (self.y + y) * self.stride + self.x;
+        return &mut self.pixels[start..start + self.width];
+    }
+
+    /// Replaces every pixel of the view with `pixel`.
+    pub fn fill(&mut self, pixel: Rgba<f32>) {
+        for y in 0..self.height {
+            self.row_mut(y).fill(pixel);
+        }
+    }
+
+    /// # Blit
+    /// Combines `src` with the view using `op`, with
+    /// its top left corner at `(x, y)` relative to the
+    /// view. Parts falling outside of it are cut off.
+    /// `src` is first converted to the color space of
+    /// the viewed image.
+    pub fn blit(&mut self, src: &Image, x: i64, y: i64, op: Operator) {
+        let src = converted(src, self.color_space);
+        let (dx, dy, w, h) = clip(self.width, self.height, x, y, src.width(), src.height());
+        let (sx, sy) = ((dx as i64 - x) as usize, (dy as i64 - y) as usize);
+        for row in 0..h {
+            let src = &src.pixels[(sy + row) * src.width() + sx..][..w];
+            for (dst, src) in self.row_mut(dy + row)[dx..dx + w].iter_mut().zip(src) {
+                *dst = op.apply(*src, *dst);
+            }
+        }
+    }
+}
+
+/// # Resize filter
+/// The reconstruction filters used by [`Image::resize`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResizeFilter {
+    /// Averages the pixels covered by each new one.
+    /// Blocky when enlarging.
+    Box,
+    /// The Mitchell-Netravali cubic, with B = C = 1/3.
+    /// Smooth, with very little ringing.
+    Mitchell,
+    /// A three lobed Lanczos window. The sharpest,
+    /// at the cost of some ringing.
+    #[default]
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    /// How far from its center the filter reaches,
+    /// in pixels.
+    pub fn support(&self) -> f32 {
+        return match self {
+            ResizeFilter::Box => 0.5,
+            ResizeFilter::Mitchell => 2.0,
+            ResizeFilter::Lanczos3 => 3.0,
+        };
+    }
+
+    /// Evaluates the filter at distance `x` from
+    /// its center.
+    pub fn eval(&self, x: f32) -> f32 {
+        let x = x.abs();
+        return match self {
+            ResizeFilter::Box => if x <= 0.5 { 1.0 } else { 0.0 },
+            ResizeFilter::Mitchell => {
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                if x < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)) / 6.0
+                } else if x < 2.0 {
+                    ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+                } else {
+                    0.0
+                }
+            }
+            ResizeFilter::Lanczos3 => {
+                if x < 1e-6 { return 1.0; }
+                if x >= 3.0 { return 0.0; }
+                let px = std::f32::consts::PI * x;
+                3.0 * px.sin() * (px / 3.0).sin() / (px * px)
+            }
+        };
+    }
+}
+
+// For each of the `dst` new pixels, the first of the `src`
+// old ones it reads and the normalized weight of each.
+fn contributions(src: usize, dst: usize, filter: ResizeFilter) -> Vec<(usize, Vec<f32>)> {
+    let scale = dst as f32 / src as f32;
+    // When shrinking the filter is stretched, so that every
+    // old pixel contributes.
+    let stretch = f32::max(1.0 / scale, 1.0);
+    let support = filter.support() * stretch;
+    return (0..dst).map(|i| {
+        let center = (i as f32 + 0.5) / scale;
+        let start = ((center - support).floor().max(0.0) as usize).min(src - 1);
+        let end = ((center + support).ceil() as usize).clamp(start + 1, src);
+        let mut weights: Vec<f32> = (start..end)
+            .map(|j| filter.eval((j as f32 + 0.5 - center) / stretch))
+            .collect();
+        let sum: f32 = weights.iter().sum();
+        if sum.abs() > 1e-6 {
+            weights.iter_mut().for_each(|w| *w /= sum);
+        } else {
+            // Only possible with the box filter landing between
+            // two pixels; take the nearest one.
+            weights.iter_mut().for_each(|w| *w = 0.0);
+            let nearest = (center.floor() as usize).clamp(start, end - 1);
+            weights[nearest - start] = 1.0;
+        }
+        return (start, weights);
+    }).collect();
+}
+
+fn weigh(pixels: impl Iterator<Item = (Rgba<f32>, f32)>) -> Rgba<f32> {
+    let mut out = [0.0f32; 4];
+    for (pixel, w) in pixels {
+        out[0] += pixel.r * w;
+        out[1] += pixel.g * w;
+        out[2] += pixel.b * w;
+        out[3] += pixel.a * w;
+    }
+    // Negative lobes can overshoot; keep the result a valid
+    // premultiplied color.
+    let a = out[3].clamp(0.0, 1.0);
+    return Rgba::from([out[0].clamp(0.0, a), out[1].clamp(0.0, a), out[2].clamp(0.0, a), a]);
+}
+
+// `image` in the color space `to`, only copied if it
+// has to be converted.
+fn converted(image: &Image, to: ColorSpace) -> Cow<'_, Image> {
+    if image.color_space == to {
+        return Cow::Borrowed(image);
+    }
+    let mut image = image.clone();
+    image.convert(to);
+    return Cow::Owned(image);
+}
+
+// Clips the rectangle at `(x, y)` to a `width` by `height`
+// area, returning its new origin and size.
+fn clip(width: usize, height: usize, x: i64, y: i64, w: usize, h: usize) -> (usize, usize, usize, usize) {
+    let x0 = x.clamp(0, width as i64);
+    let y0 = y.clamp(0, height as i64);
+    let x1 = x.saturating_add(w as i64).clamp(x0, width as i64);
+    let y1 = y.saturating_add(h as i64).clamp(y0, height as i64);
+    return (x0 as usize, y0 as usize, (x1 - x0) as usize, (y1 - y0) as usize);
+}
@@ -1,44 +1,186 @@
-use glam::{Vec2, Vec4};
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use rgb::{HetPixel, Rgba};
+use std::sync::Arc;
+
+use glam::{Affine2, UVec2, Vec2, Vec4};
+use rayon::{iter::{IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator}, slice::{ParallelSlice, ParallelSliceMut}};
+use rgb::Rgba;
+
+use crate::{color::{convert, decode, premultiply, unpremultiply, BlendSpace, ColorSpace}, compositor::Operator, format::Target, size::{area, pixel_count, SizeError}};
 
 /// # Layer
 /// A Layer is a simple structure that holds
 /// a buffer that contains a list of coverage
 /// values for each pixel, and a material `M`,
 /// responsible for painting each pixel.
+/// 
+/// The `units` and `bounds` fields describe the
+/// coordinate system handed to the material; see
+/// [`Units`].
 #[derive(Debug)]
 pub struct Layer<'mat, M> 
 where
-    M: Shader
+    M: Shader + ?Sized
 {
-    pub size: Vec2,
+    pub size: UVec2,
     pub coverage: Vec<f32>,
     pub material: &'mat M,
+    pub units: Units,
+    /// AABB of the painted path, as returned by
+    /// [`crate::bezier::Bezier::bb`].
+    pub bounds: Vec4,
+    /// Device pixel ratio: the number of pixels per
+    /// unit of the path's coordinates. Materials are
+    /// evaluated in those units, so they look the same
+    /// whatever the ratio; see [`Layer::shader_coords`].
+    pub scale: f32,
+    /// Materials return sRGB colors. With
+    /// [`BlendSpace::Linear`] they are decoded to
+    /// linear light when painted, so that edges
+    /// and later compositing happen in linear light,
+    /// and [`Layer::paint`] returns a linear sRGB
+    /// image. Defaults to [`BlendSpace::Srgb`], which
+    /// paints sRGB images.
+    pub blend_space: BlendSpace,
 }
 
-impl<'mat, M> Layer<'mat, M> where M: Shader {
-    pub fn new(size: Vec2, material: &M) -> Layer<M> {
-        return Layer {
+impl<'mat, M> Layer<'mat, M> where M: Shader + ?Sized {
+    /// Creates an empty layer, failing if `size` is
+    /// zero or too large; see [`area`].
+    pub fn new(size: UVec2, material: &M) -> Result<Layer<M>, SizeError> {
+        return Ok(Layer {
             size,
             material,
-            coverage: vec![0.0; (size.x * size.y) as usize]
-        };
+            coverage: vec![0.0; area(size)?],
+            units: Units::default(),
+            bounds: Vec4::from([0.0, 0.0, size.x as f32, size.y as f32]),
+            scale: 1.0,
+            blend_space: BlendSpace::default(),
+        });
     }
 
-    pub fn paint(&self) -> Image {
-        let mut image = Image::new(self.size);
-        self.coverage.iter().enumerate().for_each(|(i, cov)| {
-            if cov == &0.0 { image.push_pixel(Rgba::from([0.0, 0.0, 0.0, 0.0])); } // Skip transparent pixels
-            else {
-                let x = i as f32 % self.size.x;
-                let y = (i as f32 / self.size.x).floor();
-                let pixel = self.material.fill(x, y, self.size.x, self.size.y);
-                image.push_pixel(pixel.map_colors_same(|col| col * (pixel.a * cov)));
+    /// # Shader coordinates
+    /// Maps the pixel at `p` into the coordinate
+    /// system requested by `self.units`. Returns the
+    /// mapped point together with the size of the
+    /// reference box, which the material receives
+    /// as `w` and `h`.
+    /// 
+    /// Pixels are first divided by `self.scale`, going
+    /// back to the path's own (logical) coordinates.
+    pub fn shader_coords(&self, p: Vec2) -> (Vec2, Vec2) {
+        let p = p / self.scale;
+        match self.units {
+            Units::UserSpace(transform) => (transform.transform_point2(p), self.size.as_vec2() / self.scale),
+            Units::ObjectBoundingBox => {
+                let min = Vec2::new(self.bounds.x, self.bounds.y);
+                let max = Vec2::new(self.bounds.z, self.bounds.w);
+                // A degenerate box (e.g. a perfectly flat path) would
+                // make the material divide by zero.
+                (p - min, Vec2::max(max - min, Vec2::splat(f32::EPSILON)))
             }
-        });
+        }
+    }
+
+    /// # Clip
+    /// Restricts the layer to the given coverage,
+    /// e.g. the one of a clipping path or a mask.
+    pub fn clip(&mut self, coverage: &[f32]) {
+        self.coverage.iter_mut().zip(coverage).for_each(|(cov, clip)| *cov *= clip);
+    }
+
+    /// # Shader step
+    /// The offset in shader space between two horizontally
+    /// adjacent pixels, used to evaluate whole spans.
+    pub fn shader_step(&self) -> Vec2 {
+        match self.units {
+            Units::UserSpace(transform) => transform.matrix2.x_axis / self.scale,
+            Units::ObjectBoundingBox => Vec2::X / self.scale,
+        }
+    }
+
+    /// # Paint
+    /// Runs the material over every covered pixel,
+    /// returning a premultiplied image, tagged as
+    /// linear sRGB when blending in linear light.
+    /// Rows are painted in parallel straight into
+    /// the final buffer; since each row only depends
+    /// on its own coverage the result is the same
+    /// as painting them one after the other.
+    pub fn paint(&self) -> Image {
+        let transparent = Rgba::from([0.0, 0.0, 0.0, 0.0]);
+        let mut pixels = vec![transparent; self.coverage.len()];
+        let width = usize::max(self.size.x as usize, 1); // chunks can't be empty
+        pixels.par_chunks_mut(width)
+            .zip(self.coverage.par_chunks(width))
+            .enumerate()
+            .for_each(|(y, (row, coverage))| self.paint_row(y, row, coverage));
+        let mut image = Image::from_pixels(self.size, pixels);
+        if self.blend_space == BlendSpace::Linear {
+            image.color_space = ColorSpace::LinearSrgb;
+        }
         return image;
     }
+
+    /// # Paint into
+    /// Paints the layer straight into a buffer owned
+    /// by the caller, combining it with the existing
+    /// content using `op`, without allocating an
+    /// [`Image`] for the whole layer.
+    pub fn paint_into(&self, target: &mut Target, op: Operator) {
+        let width = self.size.x as usize;
+        let linear = self.blend_space == BlendSpace::Linear;
+        target.draw(width, self.size.y as usize, linear, op, |y, row| {
+            let coverage = &self.coverage[y * width..y * width + row.len()];
+            self.paint_row(y, row, coverage);
+        });
+    }
+
+    fn paint_row(&self, y: usize, row: &mut [Rgba<f32>], coverage: &[f32]) {
+        let step = self.shader_step();
+        // Find runs of covered pixels and paint each one
+        // with a single call to the material.
+        let mut x = 0;
+        while x < coverage.len() {
+            if coverage[x] == 0.0 { x += 1; continue; } // Skip transparent pixels
+            let start = x;
+            while x < coverage.len() && coverage[x] != 0.0 { x += 1; }
+
+            let (p, size) = self.shader_coords(Vec2::new(start as f32, y as f32));
+            let span = &mut row[start..x];
+            self.material.fill_span(p.x, p.y, step, size.x, size.y, span);
+            for (pixel, cov) in span.iter_mut().zip(&coverage[start..x]) {
+                if self.blend_space == BlendSpace::Linear {
+                    *pixel = decode(*pixel);
+                }
+                // Coverage scales the alpha of the material.
+                pixel.a *= cov;
+                *pixel = premultiply(*pixel);
+            }
+        }
+    }
+}
+
+/// # Units
+/// The coordinate system a [`Shader`] is evaluated
+/// in, analogous to SVG's `gradientUnits` and
+/// `patternUnits` attributes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Units {
+    /// Pixel coordinates are mapped through the given
+    /// transform, while `w` and `h` are the size of the
+    /// layer. The identity transform hands the shader
+    /// plain pixel coordinates.
+    UserSpace(Affine2),
+    /// Coordinates are relative to the top left corner
+    /// of the painted path's bounding box, and `w` and
+    /// `h` are the size of that box, so that `x / w` and
+    /// `y / h` span from 0 to 1 across the shape.
+    ObjectBoundingBox,
+}
+
+impl Default for Units {
+    fn default() -> Units {
+        return Units::UserSpace(Affine2::IDENTITY);
+    }
 }
 
 
@@ -73,25 +215,210 @@ impl<'mat, M> Layer<'mat, M> where M: Shader {
 /// The fields of the struct are analogous to OpenGL's
 /// `uniforms` or attributes and can of course store any
 /// type of data.
-pub trait Shader: core::fmt::Debug {
+/// 
+/// Since layers are painted by several threads at
+/// once, shaders must be `Sync`.
+/// 
+/// Shaders return straight (non premultiplied) colors,
+/// which are premultiplied when the layer is painted.
+/// 
+/// The meaning of `x`, `y`, `w` and `h` depends on the
+/// [`Units`] of the layer being painted: by default they
+/// are the pixel coordinates and the size of the whole
+/// layer.
+pub trait Shader: core::fmt::Debug + Sync {
     fn fill(&self, _x: f32, _y: f32, _w: f32, _h: f32) -> Rgba<f32>;
+
+    /// # Fill span
+    /// Paints a horizontal run of `span.len()` pixels,
+    /// the first of which sits at `x`, `y`. Every
+    /// following pixel is `step` further away in shader
+    /// space.
+    /// 
+    /// The default implementation simply calls `fill`
+    /// once per pixel. Shaders can override it to amortize
+    /// setup costs or to evaluate several pixels at once
+    /// with SIMD.
+    fn fill_span(&self, x: f32, y: f32, step: Vec2, w: f32, h: f32, span: &mut [Rgba<f32>]) {
+        let origin = Vec2::new(x, y);
+        for (i, pixel) in span.iter_mut().enumerate() {
+            let p = origin + step * i as f32;
+            *pixel = self.fill(p.x, p.y, w, h);
+        }
+    }
 }
 
-#[derive(Debug)]
+/// # Image
+/// A buffer of pixels, stored row by row.
+/// 
+/// Pixels are always premultiplied: each color
+/// component has already been multiplied by alpha.
+/// This is what painting produces and what the
+/// compositor, masks and filters expect. Straight
+/// (non premultiplied) pixels, like the ones found
+/// in most file formats, have to be converted with
+/// [`Image::from_straight`] and [`Image::to_straight`].
+/// 
+/// Every image is tagged with the [`ColorSpace`] its
+/// pixels are in, sRGB unless told otherwise, and can
+/// carry an ICC profile to be embedded when encoded.
+#[derive(Debug, Clone)]
 pub struct Image {
-    pub size: Vec2,
+    pub size: UVec2,
     pub pixels: Vec<Rgba<f32>>,
+    pub color_space: ColorSpace,
+    /// An ICC profile describing `color_space`, if any.
+    pub icc: Option<Arc<[u8]>>,
 }
 
 impl Image {
-    pub fn new(size: Vec2) -> Image {
+    /// Creates an empty sRGB image, with room for
+    /// `size` pixels to be pushed. Fails if the size
+    /// is zero or too large.
+    pub fn new(size: UVec2) -> Result<Image, SizeError> {
+        return Ok(Image {
+            size,
+            pixels: Vec::with_capacity(area(size)?),
+            color_space: ColorSpace::default(),
+            icc: None,
+        });
+    }
+
+    /// Creates an sRGB image out of premultiplied pixels.
+    ///
+    /// # Panics
+    /// If there isn't exactly one pixel per pixel of
+    /// `size`.
+    pub fn from_pixels(size: UVec2, pixels: Vec<Rgba<f32>>) -> Image {
+        assert!(pixels.len() == pixel_count(size), "The pixels don't match the size of the image!");
         return Image {
             size,
-            pixels: Vec::with_capacity((size.x * size.y) as usize)
+            pixels,
+            color_space: ColorSpace::default(),
+            icc: None,
+        };
+    }
+
+    /// Creates an image of the given size, filled
+    /// with transparent pixels. Fails if the size is
+    /// zero or too large.
+    pub fn transparent(size: UVec2) -> Result<Image, SizeError> {
+        return Ok(Image::from_pixels(size, vec![Rgba::from([0.0, 0.0, 0.0, 0.0]); area(size)?]));
+    }
+
+    /// # From straight
+    /// Creates an image out of straight pixels,
+    /// premultiplying them.
+    ///
+    /// # Panics
+    /// Like [`Image::from_pixels`].
+    pub fn from_straight(size: UVec2, pixels: Vec<Rgba<f32>>) -> Image {
+        return Image::from_pixels(size, pixels.into_par_iter().map(premultiply).collect());
+    }
+
+    /// # With pixels
+    /// Creates an image the same size and in the same
+    /// color space as this one, but with other pixels.
+    pub fn with_pixels(&self, pixels: Vec<Rgba<f32>>) -> Image {
+        return Image {
+            size: self.size,
+            pixels,
+            color_space: self.color_space,
+            icc: self.icc.clone(),
         };
     }
 
+    /// # Set ICC
+    /// Attaches an ICC profile to the image. The
+    /// profile is not parsed: it must describe the
+    /// color space the image is tagged with.
+    pub fn set_icc(&mut self, profile: &[u8]) {
+        self.icc = Some(Arc::from(profile));
+    }
+
+    /// # Convert
+    /// Converts the pixels to another color space,
+    /// retagging the image. Any ICC profile attached
+    /// is dropped, as it no longer matches.
+    pub fn convert(&mut self, to: ColorSpace) {
+        if self.color_space == to { return; }
+        let from = self.color_space;
+        self.pixels.par_iter_mut().for_each(|pixel| {
+            *pixel = premultiply(convert(unpremultiply(*pixel), from, to));
+        });
+        self.color_space = to;
+        self.icc = None;
+    }
+
+    /// # To straight
+    /// Returns the pixels of the image as straight
+    /// colors, e.g. for encoding.
+    pub fn to_straight(&self) -> Vec<Rgba<f32>> {
+        return self.pixels.par_iter().map(|pixel| unpremultiply(*pixel)).collect();
+    }
+
+    /// Appends a premultiplied pixel.
     pub fn push_pixel(&mut self, pixel: Rgba<f32>) {
         self.pixels.push(pixel);
     }
-}
\ No newline at end of file
+
+    /// # Fade
+    /// Multiplies every pixel by `opacity`.
+    pub fn fade(&mut self, opacity: f32) {
+        self.pixels.par_iter_mut().for_each(|pixel| {
+            *pixel = Rgba::from([pixel.r * opacity, pixel.g * opacity, pixel.b * opacity, pixel.a * opacity]);
+        });
+    }
+
+    /// # Modulate
+    /// Multiplies each pixel by the matching value
+    /// of `coverage`, e.g. the one of a mask.
+    pub fn modulate(&mut self, coverage: &[f32]) {
+        self.pixels.par_iter_mut().zip(coverage.par_iter()).for_each(|(pixel, cov)| {
+            *pixel = Rgba::from([pixel.r * cov, pixel.g * cov, pixel.b * cov, pixel.a * cov]);
+        });
+    }
+}
+#[cfg(test)]
+mod tests {
+    use glam::UVec2;
+    use rgb::Rgba;
+
+    use crate::{compositor::{Compositor, Operator}, fills::Solid};
+
+    use super::{Image, Layer};
+
+    fn assert_pixels(a: &[Rgba<f32>], b: &[[f32; 4]]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b) {
+            let error = [a.r - b[0], a.g - b[1], a.b - b[2], a.a - b[3]];
+            assert!(error.iter().all(|e| e.abs() < 1e-6), "{a:?} != {b:?}");
+        }
+    }
+
+    #[test]
+    fn paint_premultiplied() {
+        // Coverage scales alpha, and colors are multiplied
+        // by the resulting alpha.
+        let material = Solid::new([0.8, 0.4, 0.2, 0.5]);
+        let mut layer = Layer::new(UVec2::new(3, 1), &material).unwrap();
+        layer.coverage = vec![0.0, 0.5, 1.0];
+        let image = layer.paint();
+        assert_pixels(&image.pixels, &[[0.0; 4], [0.2, 0.1, 0.05, 0.25], [0.4, 0.2, 0.1, 0.5]]);
+
+        // Which the compositor expects.
+        let white = Image::from_pixels(image.size, vec![Rgba::from([1.0; 4]); 3]);
+        let mut compositor = Compositor::from_image(white);
+        compositor.composite(&image, Operator::SrcOver);
+        assert_pixels(&compositor.finish().pixels, &[[1.0; 4], [0.95, 0.85, 0.8, 1.0], [0.9, 0.7, 0.6, 1.0]]);
+    }
+
+    #[test]
+    fn straight_conversions() {
+        let straight = vec![Rgba::from([0.8, 0.4, 0.2, 0.5]), Rgba::from([1.0, 1.0, 1.0, 0.0])];
+        let image = Image::from_straight(UVec2::new(2, 1), straight);
+        assert_pixels(&image.pixels, &[[0.4, 0.2, 0.1, 0.5], [0.0; 4]]);
+        // The color of transparent pixels is lost.
+        assert_pixels(&image.to_straight(), &[[0.8, 0.4, 0.2, 0.5], [0.0; 4]]);
+    }
+}
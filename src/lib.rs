@@ -3,4 +3,13 @@ pub mod shape;
 pub mod path;
 pub mod layer;
 pub mod render;
-pub mod fills;
\ No newline at end of file
+pub mod fills;
+pub mod color;
+pub mod compositor;
+pub mod scene;
+pub mod mask;
+pub mod filter;
+pub mod codec;
+pub mod format;
+pub mod image;
+pub mod size;
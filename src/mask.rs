@@ -0,0 +1,93 @@
+//! # Masks
+//! Soft masks, using the pixels of an [`Image`] to
+//! modulate the coverage of a layer, like SVG's
+//! `<mask>` or PDF soft masks.
+
+use std::borrow::Cow;
+
+use glam::UVec2;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{color::ColorSpace, image::ResizeFilter, layer::{Image, Layer, Shader}};
+
+/// # Mask kind
+/// Which value of each mask pixel is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MaskKind {
+    /// The alpha channel.
+    Alpha,
+    /// The relative luminance of the (premultiplied)
+    /// color, so that white shows the layer and black
+    /// or transparent hides it. It is computed in
+    /// linear sRGB, as SVG does with `linearRGB`, so
+    /// masks in other color spaces are converted first.
+    #[default]
+    Luminance,
+}
+
+#[derive(Debug)]
+pub struct Mask {
+    pub image: Image,
+    pub kind: MaskKind,
+}
+
+impl Mask {
+    pub fn new(image: Image, kind: MaskKind) -> Mask {
+        return Mask { image, kind };
+    }
+
+    /// Paints `layer` and uses the result as a mask.
+    pub fn from_layer<M: Shader + ?Sized>(layer: &Layer<M>, kind: MaskKind) -> Mask {
+        return Mask::new(layer.paint(), kind);
+    }
+
+    /// # Coverage
+    /// Returns the value of the mask for each pixel,
+    /// for a layer of the given size. A mask of a
+    /// different size, e.g. one made at a lower device
+    /// pixel ratio, is stretched to cover the layer.
+    pub fn coverage(&self, size: UVec2) -> Vec<f32> {
+        let mut image = if size == self.image.size {
+            Cow::Borrowed(&self.image)
+        } else {
+            Cow::Owned(self.image.resize(size.x as usize, size.y as usize, ResizeFilter::default()))
+        };
+        if self.kind == MaskKind::Luminance && image.color_space != ColorSpace::LinearSrgb {
+            image.to_mut().convert(ColorSpace::LinearSrgb);
+        }
+        return image.pixels.par_iter().map(|pixel| match self.kind {
+            MaskKind::Alpha => pixel.a,
+            // Rec. 709 coefficients, as used by SVG.
+            MaskKind::Luminance => 0.2125 * pixel.r + 0.7154 * pixel.g + 0.0721 * pixel.b,
+        }).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::UVec2;
+    use rgb::Rgba;
+
+    use crate::{color::ColorSpace, layer::Image};
+
+    use super::{Mask, MaskKind};
+
+    #[test]
+    fn luminance_in_linear_srgb() {
+        let grey = Image::from_pixels(UVec2::ONE, vec![Rgba::from([0.5, 0.5, 0.5, 1.0])]);
+        let mut linear = grey.clone();
+        linear.color_space = ColorSpace::LinearSrgb;
+        // sRGB 0.5 is about 0.214 in linear light.
+        let coverage = Mask::new(grey.clone(), MaskKind::Luminance).coverage(UVec2::ONE);
+        assert!((coverage[0] - 0.2140).abs() < 1e-3, "{coverage:?}");
+        let coverage = Mask::new(linear, MaskKind::Luminance).coverage(UVec2::ONE);
+        assert!((coverage[0] - 0.5).abs() < 1e-6, "{coverage:?}");
+        assert_eq!(Mask::new(grey, MaskKind::Alpha).coverage(UVec2::ONE), [1.0]);
+
+        // Pure red weighs the same whatever the mask was
+        // encoded with.
+        let red = Image::from_pixels(UVec2::ONE, vec![Rgba::from([0.5, 0.0, 0.0, 0.5])]);
+        let coverage = Mask::new(red, MaskKind::Luminance).coverage(UVec2::ONE);
+        assert!((coverage[0] - 0.2125 * 0.5).abs() < 1e-6, "{coverage:?}");
+    }
+}
@@ -1,9 +1,11 @@
 use std::sync::Arc;
-use glam::{Vec2, Vec4, Vec4Swizzles};
+use glam::{Affine2, Vec2, Vec4, Vec4Swizzles};
 
 use crate::{bezier::Bezier, shape::Shape};
 
-#[derive(Debug)]
+pub mod shapes;
+
+#[derive(Debug, Clone)]
 pub struct Path {
     data: Vec<Arc<dyn Bezier>>
 }
@@ -20,6 +22,17 @@ impl Path {
         }
         return &self.data[index];
     }
+
+    pub fn curves(&self) -> &[Arc<dyn Bezier>] {
+        return &self.data;
+    }
+
+    /// # Transformed
+    /// Returns a copy of the path with every curve
+    /// mapped through `transform`.
+    pub fn transformed(&self, transform: Affine2) -> Path {
+        return Path::new(self.data.iter().map(|curve| curve.transform(transform)).collect());
+    }
 }
 
 impl Shape for Path {
@@ -78,6 +91,10 @@ impl Bezier for Path {
     fn split(&self, t: f32) -> Vec<Arc<dyn Bezier>> {
         return self.get_curve_at_t(t).split(t);
     }
+
+    fn transform(&self, transform: Affine2) -> Arc<dyn Bezier> {
+        return Arc::new(self.transformed(transform));
+    }
     
     fn bb(&self) -> Vec4 {
         let mut min = Vec2::INFINITY;
@@ -86,8 +103,8 @@ impl Bezier for Path {
             let minmax = element.bb();
             if minmax.x < min.x { min.x = minmax.x }
             if minmax.y < min.y { min.y = minmax.y }
-            if minmax.z < max.x { max.x = minmax.z }
-            if minmax.w < max.y { max.y = minmax.w }
+            if minmax.z > max.x { max.x = minmax.z }
+            if minmax.w > max.y { max.y = minmax.w }
         }
         return Vec4::from([min.x, min.y, max.x, max.y]);
     }
@@ -0,0 +1,330 @@
+//! # Shapes
+//! Constructors for common closed shapes, built out
+//! of [`Line`]s and [`Quadratic`]s.
+//!
+//! Angles are in radians and, since the y axis points
+//! down, positive ones turn clockwise on screen. Curved
+//! shapes are approximated with quadratics, as many as
+//! needed for the distance from the true outline to stay
+//! within `tolerance`, in the same units as the shape.
+
+use std::{f32::consts::{FRAC_PI_2, PI, TAU}, sync::Arc};
+
+use glam::{Affine2, Vec2};
+
+use crate::bezier::{arc::EllipticArc, line::Line, quadratic::Quadratic, Bezier};
+use super::Path;
+
+/// A tenth of a pixel, enough for the approximation
+/// of a curve to be indistinguishable from it.
+pub const DEFAULT_TOLERANCE: f32 = 0.1;
+
+/// # Corner style
+/// The shape of the corners of a rounded rectangle.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CornerStyle {
+    /// Quarter ellipses.
+    #[default]
+    Round,
+    /// Quarter superellipses with the given exponent,
+    /// for smoother corners whose curvature starts
+    /// gently. 2 gives round corners, 4 to 5 the
+    /// familiar squircle, and larger ones approach
+    /// square corners.
+    /// 
+    /// Exponents below 1 would curve inwards, and are
+    /// taken as 1, which cuts the corners straight.
+    Squircle(f32),
+}
+
+impl CornerStyle {
+    fn exponent(&self) -> f32 {
+        return match self {
+            CornerStyle::Round => 2.0,
+            // `max` also turns NaN into 1.
+            CornerStyle::Squircle(exponent) => exponent.max(1.0),
+        };
+    }
+}
+
+/// # Corners
+/// The radii of each corner of a rounded rectangle.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Corners {
+    pub top_left: f32,
+    pub top_right: f32,
+    pub bottom_right: f32,
+    pub bottom_left: f32,
+    pub style: CornerStyle,
+}
+
+impl Corners {
+    /// Round corners, all with the same radius.
+    pub fn new(radius: f32) -> Corners {
+        return Corners {
+            top_left: radius,
+            top_right: radius,
+            bottom_right: radius,
+            bottom_left: radius,
+            style: CornerStyle::Round,
+        };
+    }
+
+    pub fn set_style(&mut self, style: CornerStyle) {
+        self.style = style;
+    }
+
+    // Shrinks every radius by the same factor until adjacent
+    // corners don't overlap, like CSS does.
+    fn fit(&self, size: Vec2) -> Corners {
+        let sums = [
+            (size.x, self.top_left + self.top_right),
+            (size.x, self.bottom_left + self.bottom_right),
+            (size.y, self.top_left + self.bottom_left),
+            (size.y, self.top_right + self.bottom_right),
+        ];
+        let factor = sums.iter()
+            .filter(|(_, sum)| *sum > 0.0)
+            .fold(1.0, |factor, (side, sum)| f32::min(factor, side / sum));
+        let radius = |r: f32| f32::max(r, 0.0) * factor;
+        return Corners {
+            top_left: radius(self.top_left),
+            top_right: radius(self.top_right),
+            bottom_right: radius(self.bottom_right),
+            bottom_left: radius(self.bottom_left),
+            style: self.style,
+        };
+    }
+}
+
+impl Path {
+    /// # Polygon
+    /// A closed polygon through `points`, in order.
+    pub fn polygon(points: &[Vec2]) -> Path {
+        let curves = (0..points.len())
+            .map(|i| Arc::new(Line::new(points[i], points[(i + 1) % points.len()])) as Arc<dyn Bezier>)
+            .collect();
+        return closed(curves);
+    }
+
+    /// # Rectangle
+    /// An axis aligned rectangle, whose top left
+    /// corner is at `min`.
+    pub fn rectangle(min: Vec2, size: Vec2) -> Path {
+        return Path::polygon(&[min, min + Vec2::new(size.x, 0.0), min + size, min + Vec2::new(0.0, size.y)]);
+    }
+
+    /// # Rotated rectangle
+    /// A rectangle centered on `center`, turned by
+    /// `angle` around it.
+    pub fn rotated_rectangle(center: Vec2, size: Vec2, angle: f32) -> Path {
+        let transform = Affine2::from_angle_translation(angle, center);
+        let half = size / 2.0;
+        let corners = [Vec2::new(-half.x, -half.y), Vec2::new(half.x, -half.y), half, Vec2::new(-half.x, half.y)];
+        return Path::polygon(&corners.map(|corner| transform.transform_point2(corner)));
+    }
+
+    /// # Rounded rectangle
+    /// An axis aligned rectangle, whose top left corner
+    /// is at `min`, with rounded corners. Radii too
+    /// large for the rectangle are all scaled down by
+    /// the same factor, so that corners never overlap.
+    pub fn rounded_rectangle(min: Vec2, size: Vec2, corners: Corners, tolerance: f32) -> Path {
+        let corners = corners.fit(size);
+        let max = min + size;
+        // Each corner, clockwise from the top left one: the
+        // center of its curve and the radius, followed by the
+        // direction it starts from.
+        let list = [
+            (Vec2::new(min.x + corners.top_left, min.y + corners.top_left), corners.top_left, Vec2::NEG_X),
+            (Vec2::new(max.x - corners.top_right, min.y + corners.top_right), corners.top_right, Vec2::NEG_Y),
+            (Vec2::new(max.x - corners.bottom_right, max.y - corners.bottom_right), corners.bottom_right, Vec2::X),
+            (Vec2::new(min.x + corners.bottom_left, max.y - corners.bottom_left), corners.bottom_left, Vec2::Y),
+        ];
+        let exponent = corners.style.exponent();
+        let mut curves: Vec<Arc<dyn Bezier>> = vec![];
+        for (i, (center, radius, from)) in list.iter().enumerate() {
+            // The direction the corner ends in, a quarter turn later.
+            let to = from.perp();
+            if *radius > 0.0 {
+                superellipse(*center, *from * *radius, to * *radius, exponent, (0.0, FRAC_PI_2), tolerance, &mut curves);
+            }
+            let (next, next_radius, next_from) = list[(i + 1) % list.len()];
+            curves.push(Arc::new(Line::new(*center + to * *radius, next + next_from * next_radius)));
+        }
+        return closed(curves);
+    }
+
+    /// # Ellipse
+    /// An ellipse centered on `center`, with the given
+    /// horizontal and vertical radii before being turned
+    /// by `angle`.
+    pub fn ellipse(center: Vec2, radii: Vec2, angle: f32, tolerance: f32) -> Path {
+        return Path::new(EllipticArc::new(center, radii, angle, 0.0, TAU).to_quadratics(tolerance));
+    }
+
+    /// # Circle
+    pub fn circle(center: Vec2, radius: f32, tolerance: f32) -> Path {
+        return Path::ellipse(center, Vec2::splat(radius), 0.0, tolerance);
+    }
+
+    /// # Pie
+    /// The slice of the circle centered on `center`
+    /// enclosed by the arc starting at the angle `start`
+    /// and spanning `sweep`, and the two radii at its
+    /// ends. A sweep of a whole turn or more gives the
+    /// whole circle.
+    pub fn pie(center: Vec2, radius: f32, start: f32, sweep: f32, tolerance: f32) -> Path {
+        if sweep.abs() >= TAU {
+            return Path::circle(center, radius, tolerance);
+        }
+        let arc = EllipticArc::circular(center, radius, start, sweep);
+        let mut curves: Vec<Arc<dyn Bezier>> = vec![Arc::new(Line::new(center, arc.start_point()))];
+        curves.extend(arc.to_quadratics(tolerance));
+        curves.push(Arc::new(Line::new(arc.end_point(), center)));
+        return closed(curves);
+    }
+
+    /// # Regular polygon
+    /// A polygon with `sides` equal sides, inscribed in
+    /// the circle centered on `center`. Its first vertex
+    /// points up, then the whole polygon is turned by
+    /// `angle`.
+    pub fn regular_polygon(center: Vec2, radius: f32, sides: usize, angle: f32) -> Path {
+        let points = (0..sides)
+            .map(|i| center + Vec2::from_angle(angle - FRAC_PI_2 + TAU * i as f32 / sides as f32) * radius)
+            .collect::<Vec<Vec2>>();
+        return Path::polygon(&points);
+    }
+
+    /// # Star
+    /// A star with `points` tips on the circle of radius
+    /// `outer`, and as many inner vertices on the circle
+    /// of radius `inner`, both centered on `center`. Its
+    /// first tip points up, then the whole star is turned
+    /// by `angle`.
+    pub fn star(center: Vec2, outer: f32, inner: f32, points: usize, angle: f32) -> Path {
+        let vertices = (0..points * 2).map(|i| {
+            let radius = if i % 2 == 0 { outer } else { inner };
+            return center + Vec2::from_angle(angle - FRAC_PI_2 + PI * i as f32 / points as f32) * radius;
+        }).collect::<Vec<Vec2>>();
+        return Path::polygon(&vertices);
+    }
+}
+
+// The rasterizer expects every curve to go either up or down,
+// so quadratics turning around are split, and horizontal lines,
+// which no scanline can cross, are dropped.
+fn closed(curves: Vec<Arc<dyn Bezier>>) -> Path {
+    return Path::new(curves.iter().flat_map(|curve| curve.fix()).collect());
+}
+
+// Approximates the arc going from `t0` to `t1` of the superellipse
+// `center + x * cos(t)^(2 / n) + y * sin(t)^(2 / n)`, with the powers
+// keeping the sign of their base. With `n = 2` it is an ellipse, and
+// `t` is the angle of the point before being stretched by `x` and `y`.
+fn superellipse(center: Vec2, x: Vec2, y: Vec2, n: f32, (t0, t1): (f32, f32), tolerance: f32, out: &mut Vec<Arc<dyn Bezier>>) {
+    let p = 2.0 / n;
+    let pow = |base: f32, exp: f32| base.signum() * base.abs().powf(exp);
+    let point = |t: f32| center + x * pow(t.cos(), p) + y * pow(t.sin(), p);
+    // The derivative, rescaled so that it doesn't vanish or blow up
+    // where cos(t) or sin(t) is zero.
+    let tangent = |t: f32| y * pow(t.cos(), 2.0 - p) - x * pow(t.sin(), 2.0 - p);
+    let quads = Quadratic::approximate(point, tangent, t0, t1, tolerance);
+    out.extend(quads.into_iter().map(|quad| Arc::new(quad) as Arc<dyn Bezier>));
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+
+    use super::{CornerStyle, Corners, Path, DEFAULT_TOLERANCE};
+
+    fn samples(path: &Path) -> Vec<Vec2> {
+        return path.curves().iter().flat_map(|curve| (0..=16).map(|i| curve.t(i as f32 / 16.0))).collect();
+    }
+
+    #[test]
+    fn rounded_rectangle_corners() {
+        let (min, size) = (Vec2::new(10.0, 20.0), Vec2::new(100.0, 60.0));
+        let max = min + size;
+        let mut corners = Corners::new(0.0);
+        (corners.top_left, corners.top_right, corners.bottom_right, corners.bottom_left) = (5.0, 10.0, 15.0, 20.0);
+        let points = samples(&Path::rounded_rectangle(min, size, corners, DEFAULT_TOLERANCE));
+        assert!(points.iter().all(|p| p.cmpge(min - 1e-3).all() && p.cmple(max + 1e-3).all()));
+
+        // Each corner bulges outwards, away from its center.
+        let list = [
+            (Vec2::new(min.x + 5.0, min.y + 5.0), 5.0, Vec2::new(-1.0, -1.0)),
+            (Vec2::new(max.x - 10.0, min.y + 10.0), 10.0, Vec2::new(1.0, -1.0)),
+            (Vec2::new(max.x - 15.0, max.y - 15.0), 15.0, Vec2::new(1.0, 1.0)),
+            (Vec2::new(min.x + 20.0, max.y - 20.0), 20.0, Vec2::new(-1.0, 1.0)),
+        ];
+        for (center, radius, outwards) in list {
+            let corner = points.iter().filter(|p| ((**p - center) * outwards).cmpgt(Vec2::splat(1e-3)).all()).collect::<Vec<_>>();
+            assert!(corner.len() > 4);
+            for p in corner {
+                assert!((p.distance(center) - radius).abs() <= DEFAULT_TOLERANCE, "{p} is off the corner at {center}");
+            }
+            let middle = center + outwards.normalize() * radius;
+            assert!(points.iter().any(|p| p.distance(middle) < 0.5), "no point near {middle}");
+        }
+    }
+
+    #[test]
+    fn rounded_rectangle_fit() {
+        // Radii are scaled down so that the corners of each
+        // side add up to its length at most.
+        let (min, size) = (Vec2::ZERO, Vec2::new(100.0, 60.0));
+        let points = samples(&Path::rounded_rectangle(min, size, Corners::new(80.0), DEFAULT_TOLERANCE));
+        let center = Vec2::new(30.0, 30.0);
+        assert!(points.iter().filter(|p| p.x < center.x && p.y < center.y).all(|p| (p.distance(center) - 30.0).abs() <= DEFAULT_TOLERANCE));
+        assert!(points.iter().any(|p| p.distance(Vec2::new(0.0, 30.0)) < 1e-3));
+    }
+
+    #[test]
+    fn squircle_corners() {
+        let (min, size) = (Vec2::ZERO, Vec2::new(100.0, 100.0));
+        let mut corners = Corners::new(40.0);
+        corners.set_style(CornerStyle::Squircle(4.0));
+        let points = samples(&Path::rounded_rectangle(min, size, corners, DEFAULT_TOLERANCE));
+        assert!(points.iter().all(|p| p.cmpge(min - 1e-3).all() && p.cmple(size + 1e-3).all()));
+        // Squircles reach further into the corner than circles.
+        let center = Vec2::splat(40.0);
+        let middle = center - Vec2::splat(40.0 * 0.5f32.powf(0.25));
+        assert!(points.iter().any(|p| p.distance(middle) < 0.5), "no point near {middle}");
+    }
+
+    #[test]
+    fn squircle_exponents() {
+        let (min, size) = (Vec2::ZERO, Vec2::new(100.0, 100.0));
+        let mut corners = Corners::new(40.0);
+        for exponent in [-3.0, 0.0, 0.5, 1.0, f32::NAN, 1000.0, f32::INFINITY] {
+            corners.set_style(CornerStyle::Squircle(exponent));
+            let points = samples(&Path::rounded_rectangle(min, size, corners, DEFAULT_TOLERANCE));
+            assert!(points.iter().all(|p| p.is_finite() && p.cmpge(min - 1e-3).all() && p.cmple(size + 1e-3).all()), "{exponent}");
+            // Corners never curve inwards past the chamfer.
+            let corner = points.iter().filter(|p| p.x < 40.0 && p.y < 40.0).collect::<Vec<_>>();
+            assert!(!corner.is_empty());
+            assert!(corner.iter().all(|p| p.x + p.y <= 40.0 + DEFAULT_TOLERANCE), "{exponent}");
+        }
+    }
+
+    #[test]
+    fn ellipse_tolerance() {
+        let (center, radii, angle) = (Vec2::new(60.0, 50.0), Vec2::new(40.0, 30.0), 0.3);
+        let mut count = 0;
+        for tolerance in [1.0, DEFAULT_TOLERANCE, 0.01] {
+            let path = Path::ellipse(center, radii, angle, tolerance);
+            assert!(path.curves().len() > count);
+            count = path.curves().len();
+            for p in samples(&path) {
+                // The distance to the ellipse along a ray from its
+                // center, a little longer than the shortest one.
+                let p = Vec2::from_angle(-angle).rotate(p - center);
+                let on = p / (p / radii).length();
+                assert!(p.distance(on) <= tolerance * 1.1, "{} > {tolerance}", p.distance(on));
+            }
+        }
+    }
+}
@@ -1,76 +1,272 @@
-use core::f32;
+use glam::{Affine2, UVec2, Vec2};
 
-use glam::Vec2;
+use crate::{bezier::{Bezier, Direction}, color::BlendSpace, compositor::Operator, format::Target, layer::{Layer, Shader, Units}, path::Path, size::{area, pixel_count, scaled, SizeError}};
 
-use crate::{bezier::{Bezier, Direction}, layer::{Layer, Shader}, path::Path, shape::Shape};
+/// Number of scanlines shot through each row of
+/// pixels, unless told otherwise.
+pub const DEFAULT_SAMPLES: usize = 8;
 
 #[derive(Debug)]
-pub struct Renderer<'mat, M: Shader> {
+pub struct Renderer<'mat, M: Shader + ?Sized> {
     rule: FillRule,
-    size: Vec2,
+    size: UVec2,
     path: Path,
-    material: &'mat M
+    material: &'mat M,
+    units: Units,
+    samples: usize,
+    blend_space: BlendSpace,
+    scale: f32,
 }
 
-impl<'mat, M> Renderer<'mat, M> where M: Shader {
-    pub fn new(path: Path, size: Vec2, rule: FillRule, material: &'mat M) -> Renderer<M> {
-        return Renderer { path, size, rule, material };
+impl<'mat, M> Renderer<'mat, M> where M: Shader + ?Sized {
+    /// Creates a renderer drawing `path` onto a `size`
+    /// sized layer, failing if `size` is zero or too
+    /// large; see [`area`]. The path is fixed first, so
+    /// its curves may turn around vertically.
+    pub fn new(path: Path, size: UVec2, rule: FillRule, material: &'mat M) -> Result<Renderer<M>, SizeError> {
+        area(size)?;
+        let path = Path::new(path.fix());
+        return Ok(Renderer { path, size, rule, material, units: Units::default(), samples: DEFAULT_SAMPLES, blend_space: BlendSpace::default(), scale: 1.0 });
+    }
+
+    /// # Set units
+    /// Choose the coordinate system the material is
+    /// evaluated in. Defaults to plain pixel coordinates.
+    pub fn set_units(&mut self, units: Units) {
+        self.units = units;
+    }
+
+    /// # Set blend space
+    /// Choose whether the painted layer is decoded
+    /// to linear light, see [`Layer::blend_space`].
+    /// Defaults to [`BlendSpace::Srgb`].
+    pub fn set_blend_space(&mut self, space: BlendSpace) {
+        self.blend_space = space;
+    }
+
+    /// # Set samples
+    /// Choose how many scanlines are shot through each
+    /// row of pixels. Horizontal coverage is always exact,
+    /// so this only affects the quality of anti-aliasing
+    /// on nearly horizontal edges. Defaults to
+    /// [`DEFAULT_SAMPLES`].
+    pub fn set_samples(&mut self, samples: usize) {
+        self.samples = usize::max(samples, 1);
+    }
+
+    /// # Set scale
+    /// Choose the device pixel ratio, e.g. 2 for a
+    /// high density display. The layer is rendered
+    /// `scale` times larger (rounded up to whole
+    /// pixels) from the same path, while the material
+    /// keeps receiving the path's own coordinates.
+    /// Fails, leaving the scale unchanged, if `scale`
+    /// isn't a positive, finite number or the scaled
+    /// size is too large.
+    pub fn set_scale(&mut self, scale: f32) -> Result<(), SizeError> {
+        scaled(self.size, scale)?;
+        self.scale = scale;
+        return Ok(());
+    }
+
+    /// # Render into
+    /// Renders the path and paints it straight into
+    /// a buffer owned by the caller, over what it
+    /// already holds.
+    pub fn render_into(&self, target: &mut Target) {
+        self.render().paint_into(target, Operator::SrcOver);
     }
 
     // TODO: use SIMD and a lot of threads
-    // TODO: Split into scanlines
-    // NOTE: 
     pub fn render(&self) -> Layer<M> {
-        let mut layer = Layer::new(self.size, self.material);
-        let mut inters = Vec::new();
-        for (index, pixel) in layer.coverage.iter_mut().enumerate() {
-            let p = Vec2::from([index as f32 % layer.size.x, (index as f32 / layer.size.y).floor()]);
-            if index % self.size.x as usize == 0 {
-                inters.clear();
-                // If a ray hits a point shared between two curves, we decide wether the intersection
-                // counts twice (different winding direction) or once (same winding direction).
-                let mut last = f32::NAN;
-                let raw_inters = self.path.intersections(p);
-                for curr in raw_inters.iter() {
-                    if last == *curr || last <= (*curr + f32::EPSILON) && last >= (*curr - f32::EPSILON) { // Or close enough
-                        let prev = self.path.get_curve_at_t(last.floor()).direction();
-                        let next = self.path.get_curve_at_t(curr.floor()).direction();
-                        if prev == next {
-                            continue;
-                        }
-                    }
-                    last = *curr;
-                    inters.push((*curr, self.path.get_curve_at_t(*curr).direction()));
-                };
-            }            
+        // The size was checked when the renderer was made,
+        // and again when the scale was set.
+        let size = scaled(self.size, self.scale).unwrap();
+        let mut layer = Layer::new(size, self.material).unwrap();
+        layer.units = self.units;
+        layer.blend_space = self.blend_space;
+        layer.bounds = self.path.bb();
+        layer.scale = self.scale;
+        layer.coverage = if self.scale == 1.0 {
+            rasterize(&self.path, size, self.rule, self.samples)
+        } else {
+            let path = self.path.transformed(Affine2::from_scale(Vec2::splat(self.scale)));
+            rasterize(&path, size, self.rule, self.samples)
+        };
+        return layer;
+    }
+}
+
+/// # Rasterize
+/// Computes the anti-aliased coverage of `path` for
+/// every pixel of a `size` sized buffer.
+/// 
+/// Each row of pixels is crossed by `samples` evenly
+/// spaced horizontal scanlines. Along each scanline the
+/// spans lying inside the path are accumulated into the
+/// pixels they overlap, weighted by the overlap length.
+/// 
+/// Crossings are counted with the direction of the
+/// curve they belong to, so every curve has to go
+/// either up or down, as [`Bezier::fix`] makes them.
+pub fn rasterize(path: &Path, size: UVec2, rule: FillRule, samples: usize) -> Vec<f32> {
+    let width = size.x as usize;
+    let mut coverage = vec![0.0; pixel_count(size)];
+    let weight = 1.0 / samples as f32;
+    let mut crossings = Vec::new();
+    for (y, row) in coverage.chunks_mut(usize::max(width, 1)).enumerate() {
+        for sample in 0..samples {
+            let y = y as f32 + (sample as f32 + 0.5) * weight;
+            scanline(path, y, &mut crossings);
 
             let mut winding = 0;
-            for (int, dir) in &inters {
-                let int = self.path.t(*int).x;
-                if int <= p.x {
-                    match self.rule {
-                        FillRule::EvenOdd => winding += 1,
-                        FillRule::NonZero => match dir {
-                            Direction::Up   => winding += 1,
-                            Direction::Down => winding -= 1,
-                        }
+            for (i, (x, dir)) in crossings.iter().enumerate() {
+                match dir {
+                    Direction::Up   => winding += 1,
+                    Direction::Down => winding -= 1,
+                }
+                if rule.is_inside(winding) {
+                    if let Some((next, _)) = crossings.get(i + 1) {
+                        accumulate(row, *x, *next, weight);
                     }
                 }
             }
-            
-            // I'm sure there's a better way to do this
-            // FIXME: Implement AA
-            match self.rule {
-                FillRule::EvenOdd => if winding % 2 == 1 { *pixel = 1.0; },
-                FillRule::NonZero => if winding != 0 { *pixel = 1.0 }
-            }
         }
-        return layer;
     }
+    // Accumulating floats may overshoot a tiny bit.
+    coverage.iter_mut().for_each(|cov| *cov = f32::min(*cov, 1.0));
+    return coverage;
 }
 
-#[derive(Debug)]
+// Collects the x coordinates where a horizontal ray at height
+// `y` crosses the path, sorted from left to right.
+fn scanline(path: &Path, y: f32, crossings: &mut Vec<(f32, Direction)>) {
+    crossings.clear();
+    // If a ray hits a point shared between two curves, it would be
+    // counted once for each of them. Nudging the ray slightly avoids
+    // having to decide which one it belongs to.
+    let shared = path.curves().iter().any(|curve| curve.first_point().y == y || curve.last_point().y == y);
+    let y = if shared { y + 0.0001 } else { y };
+
+    for curve in path.curves() {
+        let bounds = curve.bb();
+        if y < bounds.y || y > bounds.w { continue; }
+        for t in curve.intersections(Vec2::new(0.0, y)) {
+            crossings.push((curve.t(t).x, curve.direction()));
+        }
+    }
+    crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+}
+
+// Adds `weight` times the length of the overlap between each
+// pixel of `row` and the span going from `x0` to `x1`.
+fn accumulate(row: &mut [f32], x0: f32, x1: f32, weight: f32) {
+    let x0 = x0.clamp(0.0, row.len() as f32);
+    let x1 = x1.clamp(0.0, row.len() as f32);
+    if x1 <= x0 { return; }
+    let first = x0.floor() as usize;
+    let last = usize::min(x1.ceil() as usize, row.len());
+    for (px, cov) in row[first..last].iter_mut().enumerate() {
+        let px = (first + px) as f32;
+        *cov += (f32::min(x1, px + 1.0) - f32::max(x0, px)) * weight;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FillRule {
     NonZero,
     EvenOdd
-}
\ No newline at end of file
+}
+
+impl FillRule {
+    /// # Is inside
+    /// Whether a point with the given winding number
+    /// is filled.
+    pub fn is_inside(&self, winding: i32) -> bool {
+        return match self {
+            FillRule::NonZero => winding != 0,
+            FillRule::EvenOdd => winding % 2 != 0,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use glam::{UVec2, Vec2};
+
+    use crate::{bezier::{line::Line, quadratic::Quadratic, Bezier}, color::{BlendSpace, ColorSpace}, fills::Solid, path::Path};
+
+    use super::{FillRule, Renderer};
+
+    fn coverage(path: Path, size: UVec2, rule: FillRule) -> Vec<f32> {
+        return Renderer::new(path, size, rule, &Solid::new([1.0; 4])).unwrap().render().coverage;
+    }
+
+    #[test]
+    fn square() {
+        let coverage = coverage(Path::rectangle(Vec2::new(1.0, 1.0), Vec2::splat(2.0)), UVec2::splat(4), FillRule::NonZero);
+        let expected = [
+            0.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 1.0, 0.0,
+            0.0, 1.0, 1.0, 0.0,
+            0.0, 0.0, 0.0, 0.0,
+        ];
+        assert_eq!(coverage, expected);
+    }
+
+    #[test]
+    fn blend_space() {
+        // Layers paint sRGB images unless asked for linear light.
+        let grey = Solid::new([0.5, 0.5, 0.5, 1.0]);
+        let mut renderer = Renderer::new(Path::rectangle(Vec2::ZERO, Vec2::ONE), UVec2::ONE, FillRule::NonZero, &grey).unwrap();
+        let image = renderer.render().paint();
+        assert_eq!(image.color_space, ColorSpace::Srgb);
+        assert_eq!(image.pixels[0].r, 0.5);
+        renderer.set_blend_space(BlendSpace::Linear);
+        let image = renderer.render().paint();
+        assert_eq!(image.color_space, ColorSpace::LinearSrgb);
+        assert!((image.pixels[0].r - 0.2140).abs() < 1e-3);
+    }
+
+    #[test]
+    fn half_pixel_edges() {
+        // Vertical edges are exact, horizontal ones are
+        // sampled by an even number of scanlines.
+        let coverage = coverage(Path::rectangle(Vec2::new(0.5, 0.5), Vec2::new(2.0, 1.0)), UVec2::new(3, 2), FillRule::NonZero);
+        let expected = [0.25, 0.5, 0.25, 0.25, 0.5, 0.25];
+        assert!(coverage.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-6), "{coverage:?}");
+    }
+
+    #[test]
+    fn fill_rules() {
+        // Two overlapping squares going the same way.
+        let mut curves = Path::rectangle(Vec2::ZERO, Vec2::splat(2.0)).curves().to_vec();
+        curves.extend_from_slice(Path::rectangle(Vec2::ONE, Vec2::splat(2.0)).curves());
+        let size = UVec2::splat(3);
+        let nonzero = coverage(Path::new(curves.clone()), size, FillRule::NonZero);
+        let evenodd = coverage(Path::new(curves), size, FillRule::EvenOdd);
+        assert_eq!(nonzero, [1.0, 1.0, 0.0, 1.0, 1.0, 1.0, 0.0, 1.0, 1.0]);
+        assert_eq!(evenodd, [1.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn turning_quadratic() {
+        // A parabola going up and back down, closed by its
+        // base, with a square on its right so that scanlines
+        // go on after crossing it. The area of the parabola
+        // is two thirds of 8 by 4.
+        let a = Vec2::new(0.0, 1.0);
+        let c = Vec2::new(8.0, 1.0);
+        let mut curves: Vec<Arc<dyn Bezier>> = vec![
+            Arc::new(Quadratic::new(a, Vec2::new(4.0, 9.0), c)),
+            Arc::new(Line::new(c, a)),
+        ];
+        curves.extend_from_slice(Path::rectangle(Vec2::new(10.0, 1.0), Vec2::new(2.0, 4.0)).curves());
+        for rule in [FillRule::NonZero, FillRule::EvenOdd] {
+            let area: f32 = coverage(Path::new(curves.clone()), UVec2::new(12, 6), rule).iter().sum();
+            assert!((area - 64.0 / 3.0 - 8.0).abs() < 0.05, "{area}");
+        }
+    }
+}
@@ -0,0 +1,638 @@
+//! # Scene
+//! An ordered stack of filled paths, rendered
+//! back to front into a single [`Image`].
+
+use std::sync::Arc;
+
+use glam::{Affine2, UVec2, Vec2};
+use rgb::Rgba;
+
+use crate::{bezier::Bezier, color::{decode_premultiplied, BlendSpace, ColorSpace}, compositor::{BlendMode, Compositor, Operator}, filter::Filter, image::ResizeFilter, layer::{Image, Shader, Units}, mask::Mask, path::Path, render::{rasterize, FillRule, Renderer, DEFAULT_SAMPLES}, size::{area, pixel_count, scaled, SizeError}};
+
+/// # Entry
+/// A single path in a [`Scene`], along with
+/// everything needed to paint it.
+#[derive(Debug, Clone)]
+pub struct Entry<'mat> {
+    pub path: Path,
+    pub rule: FillRule,
+    pub material: &'mat dyn Shader,
+    /// Coordinate system of the material, before
+    /// `transform` is applied.
+    pub units: Units,
+    pub opacity: f32,
+    pub blend: BlendMode,
+    /// Transform applied to the path. The material
+    /// follows it, so it is always evaluated in the
+    /// path's own coordinates.
+    pub transform: Affine2,
+    /// Clipping paths restricting the entry. When
+    /// there's more than one, their intersection is
+    /// used.
+    pub clips: Vec<Arc<Clip>>,
+    /// Soft mask modulating the entry, in scene
+    /// coordinates. Unlike the path, it doesn't
+    /// follow `transform`.
+    pub mask: Option<Arc<Mask>>,
+    /// Filters applied in order to the painted entry,
+    /// before clipping and masking.
+    pub filters: Vec<Filter>,
+}
+
+impl<'mat> Entry<'mat> {
+    /// Creates a fully opaque entry with the `Normal`
+    /// blend mode and no transform.
+    pub fn new(path: Path, rule: FillRule, material: &'mat dyn Shader) -> Entry<'mat> {
+        return Entry {
+            path,
+            rule,
+            material,
+            units: Units::default(),
+            opacity: 1.0,
+            blend: BlendMode::Normal,
+            transform: Affine2::IDENTITY,
+            clips: vec![],
+            mask: None,
+            filters: vec![],
+        };
+    }
+
+    /// # Render
+    /// Rasterizes and paints the entry on its own,
+    /// with opacity applied, in the given blend space.
+    /// The image is `scale` times larger than `size`;
+    /// see [`Renderer::set_scale`]. Fails if the scale
+    /// is invalid or the scaled size is zero or too
+    /// large.
+    ///
+    /// Entries whose transform can't be inverted, even
+    /// if only because of rounding, are flattened to
+    /// nothing, and render as a transparent image, as
+    /// in SVG.
+    pub fn render(&self, size: UVec2, scale: f32, space: BlendSpace) -> Result<Image, SizeError> {
+        let inverse = self.transform.inverse();
+        if !inverse.is_finite() {
+            return Image::transparent(scaled(size, scale)?);
+        }
+        let units = match self.units {
+            Units::UserSpace(units) => Units::UserSpace(units * inverse),
+            Units::ObjectBoundingBox => Units::ObjectBoundingBox,
+        };
+        // Rotating or skewing curves can make them turn around
+        // vertically, so they have to be fixed again.
+        let path = Path::new(self.path.transformed(self.transform).fix());
+        let mut renderer = Renderer::new(path, size, self.rule, self.material)?;
+        renderer.set_units(units);
+        renderer.set_blend_space(space);
+        renderer.set_scale(scale)?;
+        let mut layer = renderer.render();
+        let size = layer.size;
+        let mut restrictions = self.clips.iter().map(|clip| clip.coverage(size, scale)).collect::<Vec<Vec<f32>>>();
+        if let Some(mask) = &self.mask {
+            restrictions.push(mask.coverage(size));
+        }
+
+        let mut image;
+        if self.filters.is_empty() {
+            // Clipping before painting saves shading clipped pixels.
+            restrictions.iter().for_each(|coverage| layer.clip(coverage));
+            image = layer.paint();
+        } else {
+            // Filters may spread the entry outside of its clip, so
+            // they have to run first.
+            image = layer.paint();
+            for filter in &self.filters {
+                image = if scale == 1.0 { filter.apply(&image) } else { filter.scaled(scale).apply(&image) };
+            }
+            restrictions.iter().for_each(|coverage| image.modulate(coverage));
+        }
+        if self.opacity < 1.0 {
+            image.fade(self.opacity);
+        }
+        return Ok(image);
+    }
+}
+
+/// # Clip
+/// A path restricting drawing to its (anti-aliased)
+/// inside, in scene coordinates.
+#[derive(Debug, Clone)]
+pub struct Clip {
+    pub path: Path,
+    pub rule: FillRule,
+}
+
+impl Clip {
+    pub fn new(path: Path, rule: FillRule) -> Clip {
+        return Clip { path, rule };
+    }
+
+    /// # Coverage
+    /// Rasterizes the clip into a `size` sized buffer,
+    /// at a device pixel ratio of `scale`.
+    pub fn coverage(&self, size: UVec2, scale: f32) -> Vec<f32> {
+        // The clip may come with curves that turn around
+        // vertically, which the rasterizer can't handle.
+        let path = Path::new(self.path.transformed(Affine2::from_scale(Vec2::splat(scale))).fix());
+        return rasterize(&path, size, self.rule, DEFAULT_SAMPLES);
+    }
+}
+
+/// # Group
+/// A list of items rendered into an offscreen image,
+/// which is then composited as a whole with a single
+/// opacity and blend mode. This way, overlapping
+/// children don't show through each other when the
+/// group is faded.
+/// 
+/// As in PDF transparency groups:
+/// - An isolated group is drawn on a transparent
+///   backdrop, while a non-isolated one starts from
+///   what's already below it, so that its children
+///   blend with it.
+/// - In a knockout group each child is composited with
+///   the group's initial backdrop only, replacing the
+///   children below it instead of blending with them.
+#[derive(Debug, Clone)]
+pub struct Group<'mat> {
+    pub items: Vec<Item<'mat>>,
+    pub opacity: f32,
+    pub blend: BlendMode,
+    pub isolated: bool,
+    pub knockout: bool,
+    /// Soft mask applied to the result of the group.
+    pub mask: Option<Arc<Mask>>,
+    /// Filters applied in order to the result of the
+    /// group, before masking.
+    pub filters: Vec<Filter>,
+}
+
+impl<'mat> Group<'mat> {
+    /// Creates an empty, isolated, non knockout group,
+    /// fully opaque and with the `Normal` blend mode.
+    pub fn new() -> Group<'mat> {
+        return Group {
+            items: vec![],
+            opacity: 1.0,
+            blend: BlendMode::Normal,
+            isolated: true,
+            knockout: false,
+            mask: None,
+            filters: vec![],
+        };
+    }
+
+    /// # Render
+    /// Draws the children of the group over `backdrop`,
+    /// the image the group will be composited onto, and
+    /// returns the premultiplied result of the group alone.
+    /// The backdrop and the result are in the given blend
+    /// space, and drawn at a device pixel ratio of `scale`.
+    /// Fails if the scale is invalid or the scaled size
+    /// is zero or too large.
+    pub fn render(&self, size: UVec2, scale: f32, backdrop: &Image, space: BlendSpace) -> Result<Image, SizeError> {
+        let initial = if self.isolated {
+            Image::transparent(backdrop.size)?
+        } else {
+            backdrop.clone()
+        };
+        let mut result = initial.clone();
+        // Alpha of the children alone, without the backdrop.
+        let mut alpha = vec![0.0; initial.pixels.len()];
+
+        for item in &self.items {
+            let (src, blend) = match item {
+                Item::Entry(entry) => (entry.render(size, scale, space)?, entry.blend),
+                Item::Group(group) => (group.render(size, scale, &result, space)?, group.blend),
+            };
+            alpha.iter_mut().zip(&src.pixels).for_each(|(a, src)| *a += src.a - *a * src.a);
+            if self.knockout {
+                let mut compositor = Compositor::from_image(initial.clone());
+                compositor.blend(&src, blend, Operator::SrcOver);
+                let knocked = compositor.finish();
+                // The child replaces what's below it, as much as
+                // it covers each pixel.
+                for ((dst, new), src) in result.pixels.iter_mut().zip(&knocked.pixels).zip(&src.pixels) {
+                    *dst = mix(*dst, *new, src.a);
+                }
+            } else {
+                let mut compositor = Compositor::from_image(result);
+                compositor.blend(&src, blend, Operator::SrcOver);
+                result = compositor.finish();
+            }
+        }
+
+        if !self.isolated {
+            // Remove the contribution of the backdrop, so that it is
+            // not composited twice (PDF 1.7, 11.4.8).
+            for ((dst, back), alpha) in result.pixels.iter_mut().zip(&initial.pixels).zip(&alpha) {
+                *dst = remove_backdrop(*dst, *back, *alpha);
+            }
+        }
+        for filter in &self.filters {
+            result = if scale == 1.0 { filter.apply(&result) } else { filter.scaled(scale).apply(&result) };
+        }
+        if let Some(mask) = &self.mask {
+            result.modulate(&mask.coverage(result.size));
+        }
+        if self.opacity < 1.0 {
+            result.fade(self.opacity);
+        }
+        return Ok(result);
+    }
+}
+
+impl Default for Group<'_> {
+    fn default() -> Self {
+        return Group::new();
+    }
+}
+
+fn mix(a: Rgba<f32>, b: Rgba<f32>, t: f32) -> Rgba<f32> {
+    return Rgba::from([
+        (1.0 - t) * a.r + t * b.r,
+        (1.0 - t) * a.g + t * b.g,
+        (1.0 - t) * a.b + t * b.b,
+        (1.0 - t) * a.a + t * b.a,
+    ]);
+}
+
+// Given the premultiplied result of a non-isolated group, its
+// premultiplied backdrop and the alpha of its children alone,
+// returns the premultiplied color of the group by itself.
+fn remove_backdrop(result: Rgba<f32>, backdrop: Rgba<f32>, alpha: f32) -> Rgba<f32> {
+    if alpha <= 0.0 { return Rgba::from([0.0, 0.0, 0.0, 0.0]); }
+    let straight = |c: f32, a: f32| if a > 0.0 { c / a } else { 0.0 };
+    let factor = backdrop.a / alpha - backdrop.a;
+    let channel = |cn: f32, c0: f32| {
+        let (cn, c0) = (straight(cn, result.a), straight(c0, backdrop.a));
+        return (cn + (cn - c0) * factor) * alpha;
+    };
+    return Rgba::from([
+        channel(result.r, backdrop.r),
+        channel(result.g, backdrop.g),
+        channel(result.b, backdrop.b),
+        alpha,
+    ]);
+}
+
+/// # Item
+/// Anything that can be drawn in a [`Scene`].
+#[derive(Debug, Clone)]
+pub enum Item<'mat> {
+    Entry(Entry<'mat>),
+    Group(Group<'mat>),
+}
+
+/// # Scene
+/// Holds a list of [`Item`]s drawn in order, the
+/// first one being at the bottom.
+/// 
+/// Like a canvas API, the scene keeps a stack of
+/// clipping paths which is applied to every entry
+/// pushed while it is active, and can be saved and
+/// restored.
+/// 
+/// Entries pushed between `begin_group` and `end_group`
+/// are drawn into that group instead.
+/// 
+/// Items are described in logical units, and drawn
+/// at the scene's device pixel ratio; see
+/// [`Scene::set_scale`].
+#[derive(Debug)]
+pub struct Scene<'mat> {
+    size: UVec2,
+    scale: f32,
+    downsample: bool,
+    background: Rgba<f32>,
+    blend_space: BlendSpace,
+    color_space: ColorSpace,
+    // The bottom group holds the scene itself, the others
+    // are the groups that haven't been ended yet.
+    groups: Vec<Group<'mat>>,
+    clips: Vec<Arc<Clip>>,
+    // Depth of the clip stack at each `save`.
+    saved: Vec<usize>,
+}
+
+impl<'mat> Scene<'mat> {
+    /// Creates an empty scene with a transparent
+    /// background, failing if `size` is zero or
+    /// too large; see [`area`].
+    pub fn new(size: UVec2) -> Result<Scene<'mat>, SizeError> {
+        area(size)?;
+        return Ok(Scene {
+            size,
+            scale: 1.0,
+            downsample: false,
+            background: Rgba::from([0.0, 0.0, 0.0, 0.0]),
+            blend_space: BlendSpace::Linear,
+            color_space: ColorSpace::default(),
+            groups: vec![Group::new()],
+            clips: vec![],
+            saved: vec![],
+        });
+    }
+
+    /// # Set background
+    /// Sets the (premultiplied) color every entry
+    /// is drawn on.
+    pub fn set_background(&mut self, background: Rgba<f32>) {
+        self.background = background;
+    }
+
+    /// # Set blend space
+    /// Chooses whether entries are painted and blended
+    /// in linear light (the default) or as sRGB encoded
+    /// values. Either way, colors are given as sRGB
+    /// and the result is returned in the scene's
+    /// color space.
+    pub fn set_blend_space(&mut self, space: BlendSpace) {
+        self.blend_space = space;
+    }
+
+    /// # Set color space
+    /// Chooses the color space of the rendered image,
+    /// sRGB by default. Entries are still painted with
+    /// sRGB primaries, so colors from wider gamuts have
+    /// to be converted to (extended) sRGB first; see
+    /// [`crate::color::convert`].
+    pub fn set_color_space(&mut self, space: ColorSpace) {
+        self.color_space = space;
+    }
+
+    /// # Set scale
+    /// Chooses the device pixel ratio, e.g. 2 or 3 for
+    /// high density displays, or a fractional one. The
+    /// scene is rendered `scale` times larger, rounded
+    /// up to whole pixels, with paths, clips and filters
+    /// scaled to match, while materials keep receiving
+    /// logical coordinates. Fails, leaving the scale
+    /// unchanged, if `scale` isn't a positive, finite
+    /// number or the scaled size is too large.
+    pub fn set_scale(&mut self, scale: f32) -> Result<(), SizeError> {
+        scaled(self.size, scale)?;
+        self.scale = scale;
+        return Ok(());
+    }
+
+    /// # Set downsample
+    /// Chooses whether an image rendered at a scale
+    /// other than 1 is filtered back down to the
+    /// logical size, e.g. to compare the anti-aliasing
+    /// of the rasterizer with supersampling.
+    pub fn set_downsample(&mut self, downsample: bool) {
+        self.downsample = downsample;
+    }
+
+    /// # Push
+    /// Adds an entry on top of the stack, clipped by
+    /// every active clipping path.
+    pub fn push(&mut self, mut entry: Entry<'mat>) {
+        entry.clips.extend(self.clips.iter().cloned());
+        self.current().items.push(Item::Entry(entry));
+    }
+
+    /// # Begin group
+    /// Starts drawing into `group`, which will be
+    /// placed on top of the stack once ended. Any
+    /// item it already holds is drawn first.
+    pub fn begin_group(&mut self, group: Group<'mat>) {
+        self.groups.push(group);
+    }
+
+    /// # End group
+    /// Ends the last group begun, going back to
+    /// drawing into its parent. Does nothing if
+    /// there's no group to end.
+    pub fn end_group(&mut self) {
+        if self.groups.len() > 1 {
+            let group = self.groups.pop().unwrap();
+            self.current().items.push(Item::Group(group));
+        }
+    }
+
+    fn current(&mut self) -> &mut Group<'mat> {
+        return self.groups.last_mut().unwrap();
+    }
+
+    /// # Clip
+    /// Restricts the following entries to the inside
+    /// of `path`, on top of any clip already active.
+    pub fn clip(&mut self, path: Path, rule: FillRule) {
+        self.clips.push(Arc::new(Clip::new(path, rule)));
+    }
+
+    /// # Save
+    /// Remembers the current clip stack, so that
+    /// clips added later can be removed with `restore`.
+    pub fn save(&mut self) {
+        self.saved.push(self.clips.len());
+    }
+
+    /// # Restore
+    /// Returns the clip stack to the state of the
+    /// matching `save`. Does nothing if there's no
+    /// saved state.
+    pub fn restore(&mut self) {
+        if let Some(depth) = self.saved.pop() {
+            self.clips.truncate(depth);
+        }
+    }
+
+    /// Items of the scene, not counting the ones in
+    /// groups that haven't been ended yet.
+    pub fn items(&self) -> &[Item<'mat>] {
+        return &self.groups[0].items;
+    }
+
+    /// # Render
+    /// Draws every item into a new image. Groups
+    /// that haven't been ended are drawn as if they
+    /// were.
+    pub fn render(&self) -> Image {
+        let mut groups = self.groups.iter().rev().cloned();
+        let mut root = groups.next().unwrap();
+        for mut parent in groups {
+            parent.items.push(Item::Group(root));
+            root = parent;
+        }
+
+        let linear = self.blend_space == BlendSpace::Linear;
+        let background = if linear { decode_premultiplied(self.background) } else { self.background };
+        // The size was checked when the scene was made, and
+        // again when the scale was set, so rendering can't fail.
+        let size = scaled(self.size, self.scale).unwrap();
+        let pixels = vec![background; pixel_count(size)];
+        let mut target = Image::from_pixels(size, pixels);
+        if linear {
+            target.color_space = ColorSpace::LinearSrgb;
+        }
+        let mut compositor = Compositor::from_image(target);
+        for item in &root.items {
+            match item {
+                Item::Entry(entry) => {
+                    let image = entry.render(self.size, self.scale, self.blend_space).unwrap();
+                    compositor.blend(&image, entry.blend, Operator::SrcOver);
+                }
+                Item::Group(group) => {
+                    let image = group.render(self.size, self.scale, compositor.image(), self.blend_space).unwrap();
+                    compositor.blend(&image, group.blend, Operator::SrcOver);
+                }
+            }
+        }
+        let mut image = compositor.finish();
+        if self.downsample && size != self.size {
+            // Averaging in the blend space keeps edges as they
+            // were composited.
+            image = image.resize(self.size.x as usize, self.size.y as usize, ResizeFilter::Box);
+        }
+        image.convert(self.color_space);
+        return image;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Affine2, UVec2, Vec2};
+    use rgb::Rgba;
+
+    use crate::{color::BlendSpace, compositor::BlendMode, fills::Solid, layer::Image, path::Path, render::FillRule};
+
+    use super::{Entry, Group, Item, Scene};
+
+    const SIZE: UVec2 = UVec2::splat(4);
+
+    // An entry covering the whole scene.
+    fn cover(material: &Solid) -> Entry<'_> {
+        return Entry::new(Path::rectangle(Vec2::ZERO, SIZE.as_vec2()), FillRule::NonZero, material);
+    }
+
+    // Renders in sRGB, so that colors are blended as given.
+    fn render(items: Vec<Item>) -> Rgba<f32> {
+        let mut scene = Scene::new(SIZE).unwrap();
+        scene.set_blend_space(BlendSpace::Srgb);
+        for item in items {
+            match item {
+                Item::Entry(entry) => scene.push(entry),
+                Item::Group(group) => {
+                    scene.begin_group(group);
+                    scene.end_group();
+                }
+            }
+        }
+        let image = scene.render();
+        assert!(image.pixels.iter().all(|p| *p == image.pixels[0]));
+        return image.pixels[0];
+    }
+
+    fn assert_close(a: Rgba<f32>, b: [f32; 4]) {
+        let error = [a.r - b[0], a.g - b[1], a.b - b[2], a.a - b[3]];
+        assert!(error.iter().all(|e| e.abs() < 1e-5), "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn rotated_ellipses() {
+        // Two ellipses side by side, so that scanlines
+        // crossing a curve that turns around go on to
+        // cross the other ellipse.
+        let size = UVec2::new(96, 48);
+        let white = Solid::new([1.0; 4]);
+        let (radii, angle) = (Vec2::new(20.0, 8.0), 0.6);
+        let centers = [Vec2::new(26.0, 24.0), Vec2::new(70.0, 24.0)];
+        let offset = Vec2::from_angle(-angle).rotate(centers[1] - centers[0]);
+        let ellipses = |centers: [Vec2; 2], angle: f32| {
+            let mut curves = Path::ellipse(centers[0], radii, angle, 0.5).curves().to_vec();
+            curves.extend_from_slice(Path::ellipse(centers[1], radii, angle, 0.5).curves());
+            return Path::new(curves);
+        };
+        for rule in [FillRule::NonZero, FillRule::EvenOdd] {
+            let mut entry = Entry::new(ellipses([Vec2::ZERO, offset], 0.0), rule, &white);
+            entry.transform = Affine2::from_angle_translation(angle, centers[0]);
+            let rotated = entry.render(size, 1.0, BlendSpace::Linear).unwrap();
+            let entry = Entry::new(ellipses(centers, angle), rule, &white);
+            let expected = entry.render(size, 1.0, BlendSpace::Linear).unwrap();
+
+            for (a, b) in rotated.pixels.iter().zip(&expected.pixels) {
+                assert!((a.a - b.a).abs() < 0.05, "{} != {}", a.a, b.a);
+            }
+            let area: f32 = rotated.pixels.iter().map(|p| p.a).sum();
+            let exact = 2.0 * std::f32::consts::PI * radii.x * radii.y;
+            assert!((area - exact).abs() < exact * 0.01, "{area} != {exact}");
+        }
+    }
+
+    #[test]
+    fn singular_transform() {
+        let white = Solid::new([1.0; 4]);
+        let mut entry = Entry::new(Path::rectangle(Vec2::ZERO, Vec2::splat(8.0)), FillRule::NonZero, &white);
+        entry.transform = Affine2::from_scale(Vec2::new(1e-30, 1e-30));
+        let image = entry.render(UVec2::splat(8), 1.0, BlendSpace::Linear).unwrap();
+        assert!(image.pixels.iter().all(|p| p.a == 0.0));
+    }
+
+    #[test]
+    fn group_opacity() {
+        let red = Solid::new([1.0, 0.0, 0.0, 1.0]);
+        // Faded one by one, the second entry shows the
+        // first one through it.
+        let mut entries = [cover(&red), cover(&red)];
+        entries.iter_mut().for_each(|entry| entry.opacity = 0.5);
+        assert_close(render(entries.map(Item::Entry).to_vec()), [0.75, 0.0, 0.0, 0.75]);
+
+        let mut group = Group::new();
+        group.items = vec![Item::Entry(cover(&red)), Item::Entry(cover(&red))];
+        group.opacity = 0.5;
+        assert_close(render(vec![Item::Group(group)]), [0.5, 0.0, 0.0, 0.5]);
+    }
+
+    #[test]
+    fn isolated_groups() {
+        let grey = Solid::new([0.5, 0.5, 0.5, 1.0]);
+        let light = Solid::new([0.4, 0.4, 0.4, 1.0]);
+        let mut multiply = cover(&light);
+        multiply.blend = BlendMode::Multiply;
+        for (isolated, expected) in [(true, 0.4), (false, 0.2)] {
+            // An isolated group has nothing to multiply with.
+            let mut group = Group::new();
+            group.isolated = isolated;
+            group.items = vec![Item::Entry(multiply.clone())];
+            let color = render(vec![Item::Entry(cover(&grey)), Item::Group(group)]);
+            assert_close(color, [expected, expected, expected, 1.0]);
+        }
+    }
+
+    #[test]
+    fn backdrop_removal() {
+        // Half transparent white over half transparent grey.
+        let white = Solid::new([1.0, 1.0, 1.0, 0.5]);
+        let grey = Solid::new([0.5, 0.5, 0.5, 0.5]);
+        let mut group = Group::new();
+        group.isolated = false;
+        group.items = vec![Item::Entry(cover(&white))];
+
+        // The group alone is only its child, even though
+        // the child was drawn over the backdrop.
+        let backdrop = Image::from_pixels(SIZE, vec![Rgba::from([0.25, 0.25, 0.25, 0.5]); 16]);
+        let image = group.render(SIZE, 1.0, &backdrop, BlendSpace::Srgb).unwrap();
+        image.pixels.iter().for_each(|p| assert_close(*p, [0.5, 0.5, 0.5, 0.5]));
+
+        // So the backdrop isn't composited twice.
+        let color = render(vec![Item::Entry(cover(&grey)), Item::Group(group)]);
+        assert_close(color, [0.625, 0.625, 0.625, 0.75]);
+    }
+
+    #[test]
+    fn knockout_groups() {
+        let red = Solid::new([1.0, 0.0, 0.0, 1.0]);
+        let blue = Solid::new([0.0, 0.0, 1.0, 0.5]);
+        // In a knockout group the blue entry replaces half
+        // of the red one instead of being drawn over it.
+        for (knockout, expected) in [(false, [0.5, 0.0, 0.5, 1.0]), (true, [0.5, 0.0, 0.25, 0.75])] {
+            let mut group = Group::new();
+            group.knockout = knockout;
+            group.items = vec![Item::Entry(cover(&red)), Item::Entry(cover(&blue))];
+            assert_close(render(vec![Item::Group(group)]), expected);
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! # Sizes
+//! Layers, images and the buffers behind them are
+//! sized in whole pixels, with a [`UVec2`]. Sizes
+//! given by users are checked once, when a scene,
+//! renderer or layer is created, so that buffers
+//! are never silently empty or truncated.
+
+use std::fmt::{self, Display};
+
+use glam::UVec2;
+use rgb::Rgba;
+
+/// # Size error
+/// Why a size can't be used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeError {
+    /// The width or the height is zero.
+    Zero,
+    /// A buffer of this size wouldn't fit in memory.
+    TooLarge,
+    /// The scale isn't a positive, finite number.
+    InvalidScale,
+}
+
+impl Display for SizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            SizeError::Zero => write!(f, "the width and height must be larger than zero"),
+            SizeError::TooLarge => write!(f, "the size is too large to allocate"),
+            SizeError::InvalidScale => write!(f, "the scale must be positive and finite"),
+        };
+    }
+}
+
+impl std::error::Error for SizeError {}
+
+/// # Area
+/// Returns the number of pixels in a `size` sized
+/// buffer, checking that it isn't empty and that a
+/// buffer of `Rgba<f32>` pixels could be allocated.
+pub fn area(size: UVec2) -> Result<usize, SizeError> {
+    if size.x == 0 || size.y == 0 {
+        return Err(SizeError::Zero);
+    }
+    let area = (size.x as usize).checked_mul(size.y as usize).ok_or(SizeError::TooLarge)?;
+    let bytes = area.checked_mul(std::mem::size_of::<Rgba<f32>>()).ok_or(SizeError::TooLarge)?;
+    if bytes > isize::MAX as usize {
+        return Err(SizeError::TooLarge);
+    }
+    return Ok(area);
+}
+
+/// # Pixel count
+/// The number of pixels in a `size` sized buffer,
+/// which may be zero.
+///
+/// # Panics
+/// If the count overflows a `usize`.
+pub fn pixel_count(size: UVec2) -> usize {
+    return (size.x as usize).checked_mul(size.y as usize).expect("Size too large!");
+}
+
+/// # Scaled
+/// The size in device pixels of a `size` sized
+/// area drawn at a device pixel ratio of `scale`,
+/// rounded up to whole pixels, checked like
+/// [`area`]. Fails if `scale` isn't a positive,
+/// finite number.
+pub fn scaled(size: UVec2, scale: f32) -> Result<UVec2, SizeError> {
+    if !(scale.is_finite() && scale > 0.0) {
+        return Err(SizeError::InvalidScale);
+    }
+    // Sizes like 100 * 1.1 land a hair above a whole
+    // number, which shouldn't add a pixel.
+    let scaled = (size.as_vec2() * scale - 0.001).ceil();
+    if scaled.max_element() > u32::MAX as f32 {
+        return Err(SizeError::TooLarge);
+    }
+    let scaled = scaled.as_uvec2();
+    area(scaled)?;
+    return Ok(scaled);
+}